@@ -0,0 +1,188 @@
+use actix_web::http::{header, StatusCode};
+use std::time::Duration;
+
+use actix_web::web::{self, Data, Json, Path, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::settings::RoomSettings;
+use crate::stats::StatsTarget;
+use crate::vc::VcId;
+use crate::vcreg::{CreateVcError, VcRegistry};
+use crate::workers::WorkerPool;
+
+/// Operator endpoints under `/admin`, guarded by `Authorization: Bearer <ADMIN_TOKEN>`.
+pub fn configure(config: &mut web::ServiceConfig) {
+    config.service(
+        web::scope("/admin")
+            .route("/vcs", web::post().to(create))
+            .route("/vcs/{vc_id}", web::delete().to(close))
+            .route("/vcs/{vc_id}/dump", web::get().to(dump))
+            .route("/vcs/{vc_id}/{kind}/{id}/stats", web::get().to(stats)),
+    );
+}
+
+/// Without a configured token the admin API does not exist at all.
+fn authorize(request: &HttpRequest, config: &Config) -> Result<(), StatusCode> {
+    let Some(token) = &config.admin_token else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| given == token);
+    if authorized {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateVc {
+    id: String,
+    /// Seconds the room stays open without peers, defaults to `EMPTY_ROOM_TIMEOUT`.
+    #[serde(default)]
+    empty_timeout: Option<u64>,
+    #[serde(default)]
+    settings: RoomSettings,
+}
+
+/// Opens a room ahead of its peers with the given settings, answering 409 if it already exists
+/// and 400 for settings it can not honour, such as unsupported codecs.
+async fn create(
+    request: HttpRequest,
+    body: Json<CreateVc>,
+    config: Data<Config>,
+    workers: Data<WorkerPool>,
+    vc_registry: Data<VcRegistry>,
+) -> HttpResponse {
+    if let Err(status) = authorize(&request, &config) {
+        return HttpResponse::new(status);
+    }
+
+    let CreateVc {
+        id,
+        empty_timeout,
+        settings,
+    } = body.into_inner();
+    match vc_registry
+        .create_vc(
+            &workers,
+            VcId(id),
+            empty_timeout.map(Duration::from_secs),
+            settings,
+        )
+        .await
+    {
+        Ok(vc) => HttpResponse::Created().json(serde_json::json!({ "id": vc.id() })),
+        Err(error @ CreateVcError::AlreadyExists(_)) => {
+            HttpResponse::Conflict().body(error.to_string())
+        }
+        Err(error @ CreateVcError::InvalidSettings(_)) => {
+            HttpResponse::BadRequest().body(error.to_string())
+        }
+        Err(error @ CreateVcError::Unavailable(_)) => {
+            tracing::warn!("{error}");
+
+            HttpResponse::ServiceUnavailable().body(error.to_string())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CloseVc {
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Evicts every peer from a room, telling them `reason`, and closes it.
+async fn close(
+    request: HttpRequest,
+    path: Path<String>,
+    query: Query<CloseVc>,
+    config: Data<Config>,
+    vc_registry: Data<VcRegistry>,
+) -> HttpResponse {
+    if let Err(status) = authorize(&request, &config) {
+        return HttpResponse::new(status);
+    }
+
+    let reason = query
+        .into_inner()
+        .reason
+        .unwrap_or_else(|| "Room closed".to_string());
+    if vc_registry
+        .close_vc(&VcId(path.into_inner()), &reason)
+        .await
+    {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().body("Unknown vc")
+    }
+}
+
+/// One JSON document with mediasoup's dump of the room's router and every peer's transports,
+/// producers and consumers, together with the room's and each connection's state.
+async fn dump(
+    request: HttpRequest,
+    path: Path<String>,
+    config: Data<Config>,
+    vc_registry: Data<VcRegistry>,
+) -> HttpResponse {
+    if let Err(status) = authorize(&request, &config) {
+        return HttpResponse::new(status);
+    }
+
+    let Some(vc) = vc_registry.get(&VcId(path.into_inner())).await else {
+        return HttpResponse::NotFound().body("Unknown vc");
+    };
+
+    match vc.debug_dump().await {
+        Ok(dump) => HttpResponse::Ok().json(dump),
+        Err(error) => {
+            tracing::error!("{error}");
+
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Mediasoup stats of any producer, consumer or transport in a room, `kind` being one of
+/// `producers`, `consumers` or `transports`.
+async fn stats(
+    request: HttpRequest,
+    path: Path<(String, String, String)>,
+    config: Data<Config>,
+    vc_registry: Data<VcRegistry>,
+) -> HttpResponse {
+    if let Err(status) = authorize(&request, &config) {
+        return HttpResponse::new(status);
+    }
+
+    let (vc_id, kind, id) = path.into_inner();
+    let target = match StatsTarget::parse(&kind, &id) {
+        Ok(target) => target,
+        Err(error) => return HttpResponse::BadRequest().body(error),
+    };
+    let Some(vc) = vc_registry.get(&VcId(vc_id)).await else {
+        return HttpResponse::NotFound().body("Unknown vc");
+    };
+    let Some(source) = vc.stats_source(&target) else {
+        return HttpResponse::NotFound().body("Unknown stats target");
+    };
+
+    match source.get_stats().await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(error) => {
+            tracing::error!("{error}");
+
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
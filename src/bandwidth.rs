@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::peer::{Role, SessionId};
+
+/// Highest simulcast/SVC spatial layer the room budget ever asks for, mediasoup clamps it to what
+/// the producer actually sends.
+pub const MAX_SPATIAL_LAYER: u8 = 2;
+
+/// Bitrate caps in bps applied to a peer's transports, `None` leaves mediasoup's default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitrateLimits {
+    /// Per producer transport.
+    pub max_incoming: Option<u32>,
+    /// Per consumer transport.
+    pub max_outgoing: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BandwidthConfig {
+    pub participant: BitrateLimits,
+    pub spectator: BitrateLimits,
+    /// Total bps a room may send to its peers before simulcast layers are degraded.
+    pub room_budget: Option<u32>,
+    /// How often peers report their outgoing bitrate towards the room budget.
+    pub sample_interval: Duration,
+}
+
+impl BandwidthConfig {
+    pub fn limits(&self, role: Role) -> BitrateLimits {
+        match role {
+            Role::Participant => self.participant,
+            Role::Spectator => self.spectator,
+        }
+    }
+}
+
+/// Room wide outgoing bitrate, steering the highest spatial layer forwarded to consumers.
+pub struct RoomBudget {
+    budget: u32,
+    cooldown: Duration,
+    samples: HashMap<SessionId, u32>,
+    layer_cap: Option<u8>,
+    changed_at: Option<Instant>,
+}
+
+impl RoomBudget {
+    pub fn new(budget: u32, cooldown: Duration) -> Self {
+        Self {
+            budget,
+            cooldown,
+            samples: HashMap::new(),
+            layer_cap: None,
+            changed_at: None,
+        }
+    }
+
+    /// Highest spatial layer consumers should receive, `None` when unrestricted.
+    pub fn layer_cap(&self) -> Option<u8> {
+        self.layer_cap
+    }
+
+    /// Records the outgoing bitrate of a session, returning the new layer cap when it changed.
+    ///
+    /// The cap moves at most one layer per cooldown so consumers get to settle on the new layer
+    /// before the total is judged again. It only recovers below three quarters of the budget to
+    /// avoid flapping around the limit.
+    pub fn report(&mut self, session_id: SessionId, bitrate: u32) -> Option<Option<u8>> {
+        self.samples.insert(session_id, bitrate);
+
+        if self
+            .changed_at
+            .is_some_and(|changed_at| changed_at.elapsed() < self.cooldown)
+        {
+            return None;
+        }
+
+        let total: u64 = self
+            .samples
+            .values()
+            .map(|&bitrate| u64::from(bitrate))
+            .sum();
+        let current = self.layer_cap.unwrap_or(MAX_SPATIAL_LAYER);
+        let layer_cap = if total > u64::from(self.budget) {
+            Some(current.saturating_sub(1))
+        } else if total < u64::from(self.budget) * 3 / 4 && self.layer_cap.is_some() {
+            Some(current + 1).filter(|&layer| layer < MAX_SPATIAL_LAYER)
+        } else {
+            return None;
+        };
+        if layer_cap == self.layer_cap {
+            return None;
+        }
+
+        self.layer_cap = layer_cap;
+        self.changed_at = Some(Instant::now());
+
+        Some(layer_cap)
+    }
+
+    pub fn forget(&mut self, session_id: SessionId) {
+        self.samples.remove(&session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrades_one_layer_per_cooldown() {
+        let mut budget = RoomBudget::new(1000, Duration::from_secs(3600));
+        let session = SessionId::next();
+        assert_eq!(budget.report(session, 800), None);
+        assert_eq!(budget.report(session, 2000), Some(Some(1)));
+        // Still over budget, but the cap only just moved
+        assert_eq!(budget.report(session, 2000), None);
+        assert_eq!(budget.layer_cap(), Some(1));
+    }
+
+    #[test]
+    fn recovers_below_three_quarters() {
+        let mut budget = RoomBudget::new(1000, Duration::ZERO);
+        let (a, b) = (SessionId::next(), SessionId::next());
+        assert_eq!(budget.report(a, 600), None);
+        assert_eq!(budget.report(b, 600), Some(Some(1)));
+        assert_eq!(budget.report(b, 600), Some(Some(0)));
+        assert_eq!(budget.report(b, 600), None);
+
+        // Under budget but above three quarters of it holds the cap
+        assert_eq!(budget.report(b, 300), None);
+        assert_eq!(budget.layer_cap(), Some(0));
+
+        assert_eq!(budget.report(b, 100), Some(Some(1)));
+        assert_eq!(budget.report(b, 100), Some(None));
+        assert_eq!(budget.report(b, 100), None);
+    }
+
+    #[test]
+    fn forgotten_sessions_leave_the_total() {
+        let mut budget = RoomBudget::new(1000, Duration::ZERO);
+        let (a, b) = (SessionId::next(), SessionId::next());
+        budget.report(a, 600);
+        assert_eq!(budget.report(b, 600), Some(Some(1)));
+        budget.forget(a);
+        assert_eq!(budget.report(b, 600), Some(None));
+    }
+}
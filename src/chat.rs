@@ -0,0 +1,352 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::peer::PeerId;
+use crate::ratelimit::{RateLimit, TokenBucket};
+use crate::vc::VcId;
+
+#[derive(Debug, Clone)]
+pub struct ChatConfig {
+    /// Number of messages kept in memory and replayed to newcomers.
+    pub history_size: usize,
+    pub max_length: usize,
+    pub rate_limit: RateLimit,
+    /// Directory for per room chat logs, chat is memory only when unset. A log is created on the
+    /// room's first chat event and only retains the messages still in history: it is rewritten
+    /// without deleted messages right after a delete, and without edits and messages beyond
+    /// `history_size` whenever it is opened or has grown to twice that many lines.
+    pub log_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatMessage {
+    pub id: u64,
+    pub peer_id: PeerId,
+    pub text: String,
+    pub timestamp: u64,
+    pub edited_at: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "event")]
+pub enum ChatEvent {
+    Message(ChatMessage),
+
+    #[serde(rename_all = "camelCase")]
+    Edit {
+        id: u64,
+        text: String,
+        edited_at: u64,
+    },
+
+    Delete {
+        id: u64,
+    },
+}
+
+/// Per room chat with bounded history, optional on-disk log and per peer rate limiting.
+pub struct Chat {
+    config: ChatConfig,
+    history: VecDeque<ChatMessage>,
+    next_id: u64,
+    log: Option<ChatLog>,
+    rate_limits: HashMap<PeerId, TokenBucket>,
+}
+
+impl Chat {
+    /// Opens the chat of `vc_id`, restoring its history from the log if one is configured.
+    pub fn open(vc_id: &VcId, config: ChatConfig) -> Result<Self, String> {
+        let mut chat = Self {
+            history: VecDeque::with_capacity(config.history_size),
+            next_id: 1,
+            log: None,
+            rate_limits: HashMap::new(),
+            config,
+        };
+
+        let Some(log_dir) = &chat.config.log_dir else {
+            return Ok(chat);
+        };
+        let path = log_dir.join(format!("{}.jsonl", log_file_name(&vc_id.0)));
+
+        if path.exists() {
+            let file = File::open(&path)
+                .map_err(|error| format!("Failed to open chat log {path:?}: {error}"))?;
+            for line in BufReader::new(file).lines() {
+                let line =
+                    line.map_err(|error| format!("Failed to read chat log {path:?}: {error}"))?;
+                match serde_json::from_str(&line) {
+                    Ok(event) => chat.apply(event),
+                    Err(error) => {
+                        tracing::warn!("Skipping corrupt chat log line in {path:?}: {error}")
+                    }
+                }
+            }
+        }
+
+        let existed = path.exists();
+        let mut log = ChatLog {
+            path,
+            writer: None,
+            lines: 0,
+        };
+        if existed {
+            log.compact(&chat.history);
+        }
+        chat.log = Some(log);
+
+        Ok(chat)
+    }
+
+    pub fn history(&self) -> Vec<ChatMessage> {
+        self.history.iter().cloned().collect()
+    }
+
+    pub fn send(&mut self, peer_id: &PeerId, text: String) -> Result<ChatEvent, String> {
+        self.check_rate_limit(peer_id)?;
+        self.check_length(&text)?;
+
+        let event = ChatEvent::Message(ChatMessage {
+            id: self.next_id,
+            peer_id: peer_id.clone(),
+            text,
+            timestamp: clock::now_millis(),
+            edited_at: None,
+        });
+        self.record(event.clone());
+
+        Ok(event)
+    }
+
+    /// Edits a message, only its author may do so.
+    pub fn edit(&mut self, peer_id: &PeerId, id: u64, text: String) -> Result<ChatEvent, String> {
+        self.check_rate_limit(peer_id)?;
+        self.check_length(&text)?;
+
+        let message = self.find(id)?;
+        if &message.peer_id != peer_id {
+            return Err("Only the author can edit a message".to_string());
+        }
+
+        let event = ChatEvent::Edit {
+            id,
+            text,
+            edited_at: clock::now_millis(),
+        };
+        self.record(event.clone());
+
+        Ok(event)
+    }
+
+    /// Deletes a message, allowed for its author and for moderators.
+    pub fn delete(
+        &mut self,
+        peer_id: &PeerId,
+        id: u64,
+        moderator: bool,
+    ) -> Result<ChatEvent, String> {
+        let message = self.find(id)?;
+        if &message.peer_id != peer_id && !moderator {
+            return Err("Only the author or the host can delete a message".to_string());
+        }
+
+        let event = ChatEvent::Delete { id };
+        self.record(event.clone());
+
+        Ok(event)
+    }
+
+    pub fn forget_peer(&mut self, peer_id: &PeerId) {
+        self.rate_limits.remove(peer_id);
+    }
+
+    fn find(&self, id: u64) -> Result<&ChatMessage, String> {
+        self.history
+            .iter()
+            .find(|message| message.id == id)
+            .ok_or_else(|| format!("Unknown chat message {id}"))
+    }
+
+    fn check_rate_limit(&mut self, peer_id: &PeerId) -> Result<(), String> {
+        let limit = self.config.rate_limit;
+        let allowed = self
+            .rate_limits
+            .entry(peer_id.clone())
+            .or_insert_with(|| TokenBucket::new(limit))
+            .try_take();
+
+        if allowed {
+            Ok(())
+        } else {
+            Err("Sending chat messages too fast".to_string())
+        }
+    }
+
+    fn check_length(&self, text: &str) -> Result<(), String> {
+        if text.trim().is_empty() {
+            return Err("Chat message is empty".to_string());
+        }
+        if text.chars().count() > self.config.max_length {
+            return Err(format!(
+                "Chat message is longer than {} characters",
+                self.config.max_length
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn record(&mut self, event: ChatEvent) {
+        let line = serde_json::to_string(&event).unwrap();
+        let delete = matches!(event, ChatEvent::Delete { .. });
+        self.apply(event);
+
+        let Some(log) = &mut self.log else {
+            return;
+        };
+        // Deleted text must not stay on disk
+        if delete || log.lines >= 2 * self.config.history_size.max(1) {
+            log.compact(&self.history);
+        } else {
+            log.append(line);
+        }
+    }
+
+    fn apply(&mut self, event: ChatEvent) {
+        match event {
+            ChatEvent::Message(message) => {
+                self.next_id = self.next_id.max(message.id + 1);
+                self.history.push_back(message);
+                while self.history.len() > self.config.history_size {
+                    self.history.pop_front();
+                }
+            }
+            ChatEvent::Edit {
+                id,
+                text,
+                edited_at,
+            } => {
+                if let Some(message) = self.history.iter_mut().find(|message| message.id == id) {
+                    message.text = text;
+                    message.edited_at = Some(edited_at);
+                }
+            }
+            ChatEvent::Delete { id } => {
+                self.history.retain(|message| message.id != id);
+            }
+        }
+    }
+}
+
+/// A room's chat log, one [`ChatEvent`] per line. Writes happen on a thread of their own, so
+/// the disk never holds up the room.
+struct ChatLog {
+    path: PathBuf,
+    /// Started on the first write.
+    writer: Option<mpsc::Sender<LogWrite>>,
+    /// Lines written since the log was last compacted.
+    lines: usize,
+}
+
+enum LogWrite {
+    Append(String),
+    /// Replaces the log with one message event per message.
+    Compact(Vec<ChatMessage>),
+}
+
+impl ChatLog {
+    fn append(&mut self, line: String) {
+        self.lines += 1;
+        self.send(LogWrite::Append(line));
+    }
+
+    fn compact(&mut self, history: &VecDeque<ChatMessage>) {
+        self.lines = history.len();
+        self.send(LogWrite::Compact(history.iter().cloned().collect()));
+    }
+
+    fn send(&mut self, write: LogWrite) {
+        let writer = self.writer.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            let path = self.path.clone();
+            // Stops once the room, and with it the sender, is gone
+            std::thread::spawn(move || {
+                let mut file = None;
+                for write in receiver {
+                    if let Err(error) = write_log(&path, &mut file, write) {
+                        tracing::error!("Failed to write chat log {path:?}: {error}");
+                    }
+                }
+            });
+            sender
+        });
+        // Only fails if the thread panicked
+        let _ = writer.send(write);
+    }
+}
+
+/// Applies `write` to the log at `path`, opening `file` for appending when needed.
+fn write_log(path: &Path, file: &mut Option<File>, write: LogWrite) -> io::Result<()> {
+    match write {
+        LogWrite::Append(line) => {
+            let file = match file {
+                Some(file) => file,
+                None => file.insert(OpenOptions::new().create(true).append(true).open(path)?),
+            };
+            writeln!(file, "{line}")
+        }
+        LogWrite::Compact(history) => {
+            let temp_path = path.with_extension("jsonl.tmp");
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            for message in history {
+                let line = serde_json::to_string(&ChatEvent::Message(message)).unwrap();
+                writeln!(writer, "{line}")?;
+            }
+            writer.into_inner()?.sync_all()?;
+            fs::rename(&temp_path, path)?;
+            // Still points at the replaced file
+            *file = None;
+
+            Ok(())
+        }
+    }
+}
+
+/// Percent-encodes everything but ASCII letters, digits and `-`, so that distinct room ids never
+/// share a log.
+fn log_file_name(vc_id: &str) -> String {
+    let mut file_name = String::with_capacity(vc_id.len());
+    for byte in vc_id.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            file_name.push(char::from(byte));
+        } else {
+            write!(file_name, "%{byte:02X}").unwrap();
+        }
+    }
+
+    file_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file_names_are_distinct() {
+        assert_eq!(log_file_name("dreamh"), "dreamh");
+        assert_eq!(log_file_name("a-b"), "a-b");
+        assert_eq!(log_file_name("a/b"), "a%2Fb");
+        assert_eq!(log_file_name("a_b"), "a%5Fb");
+        assert_eq!(log_file_name("a%2Fb"), "a%252Fb");
+        assert_eq!(log_file_name("../x"), "%2E%2E%2Fx");
+        assert_eq!(log_file_name("é"), "%C3%A9");
+    }
+}
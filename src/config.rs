@@ -0,0 +1,385 @@
+use std::fmt::Debug;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::time::Duration;
+
+use mediasoup::prelude::*;
+use mediasoup::worker::{WorkerLogLevel, WorkerLogTag};
+
+use crate::bandwidth::{BandwidthConfig, BitrateLimits};
+use crate::chat::ChatConfig;
+use crate::logging::{WorkerLogLevelSetting, WorkerLogTags};
+use crate::origin::AllowedOrigins;
+use crate::producer::{KindLimits, SourceLimits};
+use crate::quality::QualityConfig;
+use crate::ratelimit::{Escalation, MessageLimitConfig, RateLimit};
+use crate::shutdown::DrainConfig;
+use crate::signal::SignalConfig;
+use crate::tls::TlsConfig;
+use crate::vc::DuplicatePeerPolicy;
+
+/// Server wide settings, read once from the environment on startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address the HTTP server binds to.
+    pub listen_addr: String,
+    /// Serve HTTPS instead of plain HTTP.
+    pub tls: Option<TlsConfig>,
+    /// Origins allowed to open WebSockets and make CORS requests.
+    pub allowed_origins: AllowedOrigins,
+    pub rtc: RtcConfig,
+    pub duplicate_peer_policy: DuplicatePeerPolicy,
+    /// Open rooms on the first join, otherwise they have to be created through the admin API.
+    pub auto_create_rooms: bool,
+    /// How long a room stays open after its last peer left, zero closes it right away. Rooms
+    /// that never had a peer stay open for at least 30 seconds.
+    pub empty_room_timeout: Duration,
+    pub chat: ChatConfig,
+    pub signals: SignalConfig,
+    pub messages: MessageLimitConfig,
+    /// Per room producer limits by source.
+    pub source_limits: SourceLimits,
+    /// Forward camera video of only this many most recent speakers to each peer. Screen shares
+    /// and shared media are always forwarded.
+    pub last_n: Option<usize>,
+    pub bandwidth: BandwidthConfig,
+    /// How often peers are pushed the scores of their producers and consumers.
+    pub score_interval: Option<Duration>,
+    pub quality: QualityConfig,
+    pub drain: DrainConfig,
+    pub capacity: CapacityConfig,
+    /// Bearer token for the `/admin` API, which is disabled without one.
+    pub admin_token: Option<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            listen_addr: env_or("LISTEN_ADDR", "0.0.0.0:4002".to_string()),
+            tls: match (env_opt("TLS_CERT"), env_opt("TLS_KEY")) {
+                (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                    cert_path,
+                    key_path,
+                    reload_interval: Some(env_or("TLS_RELOAD_INTERVAL", 300))
+                        .filter(|&seconds| seconds > 0)
+                        .map(Duration::from_secs),
+                }),
+                (None, None) => None,
+                _ => panic!("TLS_CERT and TLS_KEY must be set together"),
+            },
+            allowed_origins: env_or("ALLOWED_ORIGINS", AllowedOrigins::Any),
+            rtc: RtcConfig {
+                listen: env_opt("LISTEN_INFOS").unwrap_or_else(|| {
+                    ListenSpecs(vec![ListenSpec {
+                        protocol: Protocol::Udp,
+                        ip: env_opt("IP").expect("IP environment variable not set"),
+                        announced_ip: env_opt("ANNOUNCED_IP"),
+                    }])
+                }),
+                prefer_udp: env_or("PREFER_UDP", false),
+                prefer_tcp: env_or("PREFER_TCP", false),
+                port_range: env_opt::<PortRange>("RTC_PORT_RANGE").map(|range| range.0),
+                num_workers: env_or("NUM_WORKERS", 1),
+                webrtc_server_port: env_opt("WEBRTC_SERVER_PORT"),
+                worker_log_level: env_or(
+                    "WORKER_LOG_LEVEL",
+                    WorkerLogLevelSetting(WorkerLogLevel::Warn),
+                )
+                .0,
+                worker_log_tags: env_or("WORKER_LOG_TAGS", WorkerLogTags::default()).0,
+            },
+            duplicate_peer_policy: env_or("DUPLICATE_PEER_POLICY", DuplicatePeerPolicy::Replace),
+            auto_create_rooms: env_or("AUTO_CREATE_ROOMS", true),
+            empty_room_timeout: Duration::from_secs(env_or("EMPTY_ROOM_TIMEOUT", 30)),
+            chat: ChatConfig {
+                history_size: env_or("CHAT_HISTORY_SIZE", 100),
+                max_length: env_or("CHAT_MAX_LENGTH", 2000),
+                rate_limit: env_or("CHAT_RATE_LIMIT", RateLimit::new(5, Duration::from_secs(5))),
+                log_dir: std::env::var_os("CHAT_LOG_DIR").map(Into::into),
+            },
+            signals: SignalConfig {
+                types: env_or(
+                    "SIGNAL_TYPES",
+                    "reaction=10/10,applause=3/10".parse().unwrap(),
+                ),
+                hand_rate_limit: env_or(
+                    "HAND_RATE_LIMIT",
+                    RateLimit::new(3, Duration::from_secs(10)),
+                ),
+            },
+            messages: MessageLimitConfig {
+                max_frame_size: env_or("WS_MAX_FRAME_SIZE", 64 * 1024),
+                rates: env_or(
+                    "MESSAGE_RATE_LIMITS",
+                    "*=100/10,Notification=5/10,UpdateMetadata=5/10,Invalid=5/10"
+                        .parse()
+                        .unwrap(),
+                ),
+                escalation: Escalation {
+                    drop_after: env_or("RATE_LIMIT_DROP_AFTER", 3),
+                    disconnect_after: env_or("RATE_LIMIT_DISCONNECT_AFTER", 50),
+                    forgive_after: Duration::from_secs(env_or("RATE_LIMIT_FORGIVE_AFTER", 60)),
+                },
+            },
+            source_limits: env_or("SOURCE_LIMITS", "screen=1".parse().unwrap()),
+            last_n: env_opt("LAST_N"),
+            bandwidth: BandwidthConfig {
+                participant: BitrateLimits {
+                    max_incoming: env_opt("PARTICIPANT_MAX_INCOMING_BITRATE"),
+                    max_outgoing: env_opt("PARTICIPANT_MAX_OUTGOING_BITRATE"),
+                },
+                spectator: BitrateLimits {
+                    max_incoming: None,
+                    max_outgoing: env_opt("SPECTATOR_MAX_OUTGOING_BITRATE"),
+                },
+                room_budget: env_opt("ROOM_OUTGOING_BITRATE_BUDGET"),
+                sample_interval: Duration::from_secs(env_or("BITRATE_SAMPLE_INTERVAL", 2)),
+            },
+            score_interval: Some(env_or("SCORE_INTERVAL", 5))
+                .filter(|&seconds| seconds > 0)
+                .map(Duration::from_secs),
+            quality: QualityConfig {
+                interval: Some(env_or("QUALITY_INTERVAL", 2))
+                    .filter(|&seconds| seconds > 0)
+                    .map(Duration::from_secs),
+                window: env_or("QUALITY_WINDOW", 5),
+            },
+            drain: DrainConfig {
+                timeout: Duration::from_secs(env_or("DRAIN_TIMEOUT", 30)),
+                reconnect_after: Duration::from_secs(env_or("DRAIN_RECONNECT_AFTER", 5)),
+            },
+            capacity: CapacityConfig {
+                max_rooms: env_opt("MAX_ROOMS"),
+                max_peers: env_opt("MAX_PEERS"),
+                max_peers_per_room: env_opt("MAX_PEERS_PER_ROOM"),
+                max_producers_per_peer: env_or("MAX_PRODUCERS_PER_PEER", KindLimits::default()),
+                max_consumers_per_peer: env_opt("MAX_CONSUMERS_PER_PEER"),
+            },
+            admin_token: std::env::var("ADMIN_TOKEN").ok(),
+        }
+    }
+}
+
+/// Limits on growth, an instance at its room or peer capacity reports itself as not ready.
+#[derive(Debug, Clone)]
+pub struct CapacityConfig {
+    pub max_rooms: Option<usize>,
+    /// Across all rooms, only affects readiness.
+    pub max_peers: Option<usize>,
+    pub max_peers_per_room: Option<usize>,
+    pub max_producers_per_peer: KindLimits,
+    pub max_consumers_per_peer: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RtcConfig {
+    pub listen: ListenSpecs,
+    pub prefer_udp: bool,
+    pub prefer_tcp: bool,
+    /// Ports for transports that are not created through a WebRtcServer.
+    pub port_range: Option<RangeInclusive<u16>>,
+    pub num_workers: usize,
+    /// Run every worker's WebRTC traffic through one port, worker `i` using this port plus `i`.
+    pub webrtc_server_port: Option<u16>,
+    pub worker_log_level: WorkerLogLevel,
+    pub worker_log_tags: Vec<WorkerLogTag>,
+}
+
+/// An address to gather ICE candidates on, parsed from `<udp|tcp>/<ip>[/<announced ip>]`. Slashes
+/// keep IPv6 addresses unambiguous, e.g. `udp/::/2001:db8::1`.
+#[derive(Debug, Clone, Copy)]
+pub struct ListenSpec {
+    pub protocol: Protocol,
+    pub ip: IpAddr,
+    pub announced_ip: Option<IpAddr>,
+}
+
+impl ListenSpec {
+    pub fn listen_info(&self, port: Option<u16>) -> ListenInfo {
+        ListenInfo {
+            protocol: self.protocol,
+            ip: self.ip,
+            announced_ip: self.announced_ip,
+            port,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl FromStr for ListenSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('/');
+        let protocol = match parts.next() {
+            Some("udp") => Protocol::Udp,
+            Some("tcp") => Protocol::Tcp,
+            _ => {
+                return Err(format!(
+                    "Expected <udp|tcp>/<ip>[/<announced ip>], got {s:?}"
+                ))
+            }
+        };
+        let ip = parts
+            .next()
+            .ok_or_else(|| format!("Missing ip in {s:?}"))?
+            .parse()
+            .map_err(|error| format!("Invalid ip in {s:?}: {error}"))?;
+        let announced_ip = parts
+            .next()
+            .map(|ip| ip.parse())
+            .transpose()
+            .map_err(|error| format!("Invalid announced ip in {s:?}: {error}"))?;
+        if parts.next().is_some() {
+            return Err(format!("Unexpected trailing data in {s:?}"));
+        }
+
+        Ok(Self {
+            protocol,
+            ip,
+            announced_ip,
+        })
+    }
+}
+
+/// Comma separated, non-empty list of [`ListenSpec`]s.
+#[derive(Debug, Clone)]
+pub struct ListenSpecs(pub Vec<ListenSpec>);
+
+impl ListenSpecs {
+    pub fn webrtc_transport_listen_infos(&self) -> WebRtcTransportListenInfos {
+        let mut specs = self.0.iter();
+        let first = specs.next().expect("ListenSpecs are never empty");
+        specs.fold(
+            WebRtcTransportListenInfos::new(first.listen_info(None)),
+            |listen_infos, spec| listen_infos.insert(spec.listen_info(None)),
+        )
+    }
+
+    pub fn webrtc_server_listen_infos(&self, port: u16) -> WebRtcServerListenInfos {
+        let mut specs = self.0.iter();
+        let first = specs.next().expect("ListenSpecs are never empty");
+        specs.fold(
+            WebRtcServerListenInfos::new(first.listen_info(Some(port))),
+            |listen_infos, spec| listen_infos.insert(spec.listen_info(Some(port))),
+        )
+    }
+}
+
+impl FromStr for ListenSpecs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let specs = s
+            .split(',')
+            .filter(|spec| !spec.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if specs.is_empty() {
+            return Err("At least one listen info is required".to_string());
+        }
+
+        Ok(Self(specs))
+    }
+}
+
+/// Parsed from `<min>-<max>`.
+struct PortRange(RangeInclusive<u16>);
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Expected <min>-<max>, got {s:?}"))?;
+        let min: u16 = min
+            .trim()
+            .parse()
+            .map_err(|error| format!("Invalid port in {s:?}: {error}"))?;
+        let max: u16 = max
+            .trim()
+            .parse()
+            .map_err(|error| format!("Invalid port in {s:?}: {error}"))?;
+        if min > max {
+            return Err(format!("Empty port range {s:?}"));
+        }
+
+        Ok(Self(min..=max))
+    }
+}
+
+pub fn env_opt<T>(name: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    std::env::var(name).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|error| panic!("Invalid {name}: {error:?}"))
+    })
+}
+
+pub fn env_or<T>(name: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    env_opt(name).unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listen_spec() {
+        let spec: ListenSpec = "udp/0.0.0.0/203.0.113.1".parse().unwrap();
+        assert_eq!(spec.protocol, Protocol::Udp);
+        assert_eq!(spec.ip, "0.0.0.0".parse::<IpAddr>().unwrap());
+        assert_eq!(spec.announced_ip, Some("203.0.113.1".parse().unwrap()));
+
+        let spec: ListenSpec = "tcp/::/2001:db8::1".parse().unwrap();
+        assert_eq!(spec.protocol, Protocol::Tcp);
+        assert_eq!(spec.ip, "::".parse::<IpAddr>().unwrap());
+        assert_eq!(spec.announced_ip, Some("2001:db8::1".parse().unwrap()));
+
+        assert_eq!(
+            "udp/127.0.0.1".parse::<ListenSpec>().unwrap().announced_ip,
+            None
+        );
+        for s in [
+            "",
+            "sctp/0.0.0.0",
+            "udp",
+            "udp/localhost",
+            "udp/0.0.0.0/x",
+            "udp/0.0.0.0/1.2.3.4/5",
+        ] {
+            assert!(s.parse::<ListenSpec>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parses_listen_specs() {
+        let specs: ListenSpecs = "udp/0.0.0.0, tcp/0.0.0.0,".parse().unwrap();
+        assert_eq!(specs.0.len(), 2);
+        assert_eq!(specs.0[1].protocol, Protocol::Tcp);
+
+        for s in ["", " , ", "udp/0.0.0.0,tcp"] {
+            assert!(s.parse::<ListenSpecs>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parses_port_range() {
+        assert_eq!("40000-49999".parse::<PortRange>().unwrap().0, 40000..=49999);
+        assert_eq!("5000 - 5000".parse::<PortRange>().unwrap().0, 5000..=5000);
+        for s in ["40000", "49999-40000", "0-70000", "a-b"] {
+            assert!(s.parse::<PortRange>().is_err(), "{s:?}");
+        }
+    }
+}
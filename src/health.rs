@@ -0,0 +1,62 @@
+use actix_web::web::{self, Data};
+use actix_web::HttpResponse;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::shutdown::Drain;
+use crate::vcreg::VcRegistry;
+use crate::workers::WorkerPool;
+
+/// Load balancer probes, `/healthz` for liveness and `/readyz` for readiness.
+pub fn configure(config: &mut web::ServiceConfig) {
+    config
+        .route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz));
+}
+
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Readiness {
+    ready: bool,
+    workers: usize,
+    workers_alive: usize,
+    draining: bool,
+    rooms: usize,
+    peers: usize,
+}
+
+/// Ready while at least one worker is alive, the server is not draining and it is below its
+/// room and peer capacity.
+async fn readyz(
+    config: Data<Config>,
+    workers: Data<WorkerPool>,
+    vc_registry: Data<VcRegistry>,
+    drain: Data<Drain>,
+) -> HttpResponse {
+    let (rooms, peers) = vc_registry.usage().await;
+    let workers_alive = workers.alive_count();
+    let draining = drain.is_draining();
+    let capacity = &config.capacity;
+    let ready = workers_alive > 0
+        && !draining
+        && capacity.max_rooms.is_none_or(|max_rooms| rooms < max_rooms)
+        && capacity.max_peers.is_none_or(|max_peers| peers < max_peers);
+
+    let readiness = Readiness {
+        ready,
+        workers: workers.len(),
+        workers_alive,
+        draining,
+        rooms,
+        peers,
+    };
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
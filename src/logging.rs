@@ -0,0 +1,229 @@
+use std::str::FromStr;
+
+use mediasoup::worker::{WorkerLogLevel, WorkerLogTag};
+use tracing::level_filters::LevelFilter;
+use tracing_log::AsLog;
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global tracing subscriber and routes `log` records, mediasoup's included, into it.
+///
+/// Levels are configured per module through `RUST_LOG` (default `info`), e.g.
+/// `RUST_LOG=info,inomg::peer=debug,mediasoup=warn`. `LOG_FORMAT=json` switches to one JSON
+/// object per line carrying the current span fields, such as `vc_id` and `peer_id`.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing::subscriber::set_global_default(
+            builder
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .finish(),
+        ),
+        Ok("text") | Err(_) => tracing::subscriber::set_global_default(builder.finish()),
+        Ok(format) => panic!("Invalid LOG_FORMAT: {format:?}, expected text or json"),
+    };
+    result.expect("Failed to install tracing subscriber");
+
+    log::set_boxed_logger(Box::new(LogBridge)).expect("Failed to install log bridge");
+    log::set_max_level(LevelFilter::current().as_log());
+}
+
+/// Forwards `log` records to tracing, splitting mediasoup worker lines into fields.
+struct LogBridge;
+
+impl log::Log for LogBridge {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        // The tracing subscriber does the filtering
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if record.target() == WORKER_TARGET {
+            if let Some(line) = WorkerLine::parse(&record.args().to_string()) {
+                line.emit(record.level());
+                return;
+            }
+        }
+
+        let _ = tracing_log::format_trace(record);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Target mediasoup logs the lines written by its workers under.
+const WORKER_TARGET: &str = "mediasoup::worker";
+
+/// A worker log line such as `[id:12345] RTC::IceServer::ProcessStunPacket() | message`.
+struct WorkerLine<'a> {
+    worker_id: &'a str,
+    class: &'a str,
+    message: &'a str,
+}
+
+impl<'a> WorkerLine<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let (worker_id, rest) = line.strip_prefix("[id:")?.split_once("] ")?;
+        let (location, message) = rest.split_once(" | ")?;
+        let class = location
+            .trim_end_matches("()")
+            .rsplit_once("::")
+            .map_or(location, |(class, _function)| class);
+
+        Some(Self {
+            worker_id,
+            class,
+            message,
+        })
+    }
+
+    fn emit(&self, level: log::Level) {
+        // Only a guess, hence not logged as `tag`
+        let inferred_tag = tag_name(infer_tag(self.class));
+
+        macro_rules! emit {
+            ($level:expr) => {
+                tracing::event!(
+                    target: WORKER_TARGET,
+                    $level,
+                    worker_id = self.worker_id,
+                    class = self.class,
+                    inferred_tag,
+                    "{}",
+                    self.message
+                )
+            };
+        }
+
+        match level {
+            log::Level::Error => emit!(tracing::Level::ERROR),
+            log::Level::Warn => emit!(tracing::Level::WARN),
+            log::Level::Info => emit!(tracing::Level::INFO),
+            log::Level::Debug => emit!(tracing::Level::DEBUG),
+            log::Level::Trace => emit!(tracing::Level::TRACE),
+        }
+    }
+}
+
+/// Workers do not include the [`WorkerLogTag`] a line was logged under, so it is guessed from the
+/// C++ class that wrote it. Some tags, such as [`WorkerLogTag::Score`], never come out.
+fn infer_tag(class: &str) -> WorkerLogTag {
+    let class = class.trim_start_matches("RTC::");
+    let matches = |patterns: &[&str]| patterns.iter().any(|pattern| class.contains(pattern));
+
+    if matches(&["Ice", "Stun"]) {
+        WorkerLogTag::Ice
+    } else if matches(&["Dtls"]) {
+        WorkerLogTag::Dtls
+    } else if matches(&["Srtp"]) {
+        WorkerLogTag::Srtp
+    } else if matches(&["Sctp", "DataChannel", "DataProducer", "DataConsumer"]) {
+        WorkerLogTag::Sctp
+    } else if matches(&["Simulcast"]) {
+        WorkerLogTag::Simulcast
+    } else if matches(&["Svc"]) {
+        WorkerLogTag::Svc
+    } else if matches(&["Retransmission", "Nack"]) {
+        WorkerLogTag::Rtx
+    } else if matches(&["Rtcp"]) {
+        WorkerLogTag::Rtcp
+    } else if matches(&[
+        "CongestionControl",
+        "TransportController",
+        "RemoteBitrate",
+        "Bitrate",
+    ]) {
+        WorkerLogTag::Bwe
+    } else if matches(&["Rtp", "Producer", "Consumer", "Router"]) {
+        WorkerLogTag::Rtp
+    } else if matches(&["Channel", "Message"]) {
+        WorkerLogTag::Message
+    } else {
+        WorkerLogTag::Info
+    }
+}
+
+const WORKER_LOG_TAGS: [WorkerLogTag; 13] = [
+    WorkerLogTag::Info,
+    WorkerLogTag::Ice,
+    WorkerLogTag::Dtls,
+    WorkerLogTag::Rtp,
+    WorkerLogTag::Srtp,
+    WorkerLogTag::Rtcp,
+    WorkerLogTag::Rtx,
+    WorkerLogTag::Bwe,
+    WorkerLogTag::Score,
+    WorkerLogTag::Simulcast,
+    WorkerLogTag::Svc,
+    WorkerLogTag::Sctp,
+    WorkerLogTag::Message,
+];
+
+fn tag_name(tag: WorkerLogTag) -> &'static str {
+    match tag {
+        WorkerLogTag::Info => "info",
+        WorkerLogTag::Ice => "ice",
+        WorkerLogTag::Dtls => "dtls",
+        WorkerLogTag::Rtp => "rtp",
+        WorkerLogTag::Srtp => "srtp",
+        WorkerLogTag::Rtcp => "rtcp",
+        WorkerLogTag::Rtx => "rtx",
+        WorkerLogTag::Bwe => "bwe",
+        WorkerLogTag::Score => "score",
+        WorkerLogTag::Simulcast => "simulcast",
+        WorkerLogTag::Svc => "svc",
+        WorkerLogTag::Sctp => "sctp",
+        WorkerLogTag::Message => "message",
+    }
+}
+
+/// Level of the lines workers emit, parsed from `debug`, `warn`, `error` or `none`.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkerLogLevelSetting(pub WorkerLogLevel);
+
+impl FromStr for WorkerLogLevelSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "debug" => Ok(Self(WorkerLogLevel::Debug)),
+            "warn" => Ok(Self(WorkerLogLevel::Warn)),
+            "error" => Ok(Self(WorkerLogLevel::Error)),
+            "none" => Ok(Self(WorkerLogLevel::None)),
+            _ => Err(format!("Unknown worker log level {s:?}")),
+        }
+    }
+}
+
+/// Comma separated [`WorkerLogTag`] names, or `all`.
+#[derive(Debug, Clone)]
+pub struct WorkerLogTags(pub Vec<WorkerLogTag>);
+
+impl Default for WorkerLogTags {
+    fn default() -> Self {
+        Self(WORKER_LOG_TAGS.to_vec())
+    }
+}
+
+impl FromStr for WorkerLogTags {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "all" {
+            return Ok(Self::default());
+        }
+
+        s.split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| {
+                WORKER_LOG_TAGS
+                    .into_iter()
+                    .find(|&tag| tag_name(tag) == name.trim())
+                    .ok_or_else(|| format!("Unknown worker log tag {name:?}"))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
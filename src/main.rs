@@ -1,18 +1,43 @@
+mod admin;
+mod bandwidth;
+mod chat;
+mod clock;
+mod config;
+mod health;
+mod logging;
 mod message;
+mod origin;
 mod peer;
+mod playback;
+mod producer;
+mod quality;
+mod ratelimit;
+mod settings;
+mod shutdown;
+mod signal;
+mod stats;
+mod subscription;
+mod tls;
 mod vc;
 mod vcreg;
+mod workers;
 
 use std::num::{NonZeroU32, NonZeroU8};
+use std::sync::Arc;
 
+use actix_web::http::header;
 use actix_web::web::{Data, Payload, Query};
 use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
 use actix_web_actors::ws;
+use config::Config;
 use mediasoup::prelude::*;
-use peer::PeerConnection;
+use peer::{AutoSubscribe, PeerConnection, PeerId, PeerMetadata, PeerOptions, Role, SessionId};
 use serde::Deserialize;
+use shutdown::Drain;
+use tls::CertResolver;
 use vc::VcId;
 use vcreg::VcRegistry;
+use workers::WorkerPool;
 
 fn media_codecs() -> Vec<RtpCodecCapability> {
     vec![
@@ -67,34 +92,143 @@ fn media_codecs() -> Vec<RtpCodecCapability> {
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct QueryParameters {
+    /// Defaults to the room every client joined before rooms could be chosen.
+    #[serde(default = "default_room")]
+    room: String,
+    #[serde(default)]
+    password: Option<String>,
     user: String,
+    display_name: Option<String>,
+    avatar_url: Option<String>,
+    client_info: Option<String>,
+    /// JSON encoded, see [`PeerMetadata::app_data`].
+    app_data: Option<String>,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    auto_subscribe: String,
+    #[serde(default)]
+    lazy_transport: bool,
+}
+
+fn default_room() -> String {
+    "dreamh".to_string()
+}
+
+impl QueryParameters {
+    fn metadata(&self) -> Result<PeerMetadata, String> {
+        let app_data = match &self.app_data {
+            Some(app_data) => serde_json::from_str(app_data)
+                .map_err(|error| format!("Invalid appData: {error}"))?,
+            None => serde_json::Value::Null,
+        };
+
+        Ok(PeerMetadata {
+            display_name: self.display_name.clone(),
+            avatar_url: self.avatar_url.clone(),
+            client_info: self.client_info.clone(),
+            app_data,
+        })
+    }
+
+    fn options(&self) -> Result<PeerOptions, String> {
+        Ok(PeerOptions {
+            role: match &self.role {
+                Some(role) => role
+                    .parse::<Role>()
+                    .map_err(|error| format!("Invalid role: {error}"))?,
+                None => Role::default(),
+            },
+            auto_subscribe: self
+                .auto_subscribe
+                .parse::<AutoSubscribe>()
+                .map_err(|error| format!("Invalid autoSubscribe: {error}"))?,
+            lazy_producer_transport: self.lazy_transport,
+        })
+    }
 }
 
 async fn ws_index(
     query_parameters: Query<QueryParameters>,
     request: HttpRequest,
-    worker_manager: Data<WorkerManager>,
+    workers: Data<WorkerPool>,
     vc_registry: Data<VcRegistry>,
+    drain: Data<Drain>,
+    config: Data<Config>,
     stream: Payload,
 ) -> Result<HttpResponse, Error> {
-    let vc = vc_registry
-        .get_or_create_vc(&worker_manager, VcId("dreamh".into()))
-        .await;
+    if let Err(error) = config.allowed_origins.check(&request) {
+        return Ok(HttpResponse::Forbidden().body(error));
+    }
+
+    // Joining has effects on the room, make sure this is a WebSocket upgrade first
+    if let Err(error) = ws::handshake(&request) {
+        return Err(error.into());
+    }
+
+    if drain.is_draining() {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .insert_header((
+                header::RETRY_AFTER,
+                config.drain.reconnect_after.as_secs().to_string(),
+            ))
+            .body("Server is shutting down"));
+    }
+
+    let vc_id = VcId(query_parameters.room.clone());
+    let vc = if config.auto_create_rooms {
+        vc_registry.get_or_create_vc(&workers, vc_id).await
+    } else {
+        match vc_registry.get(&vc_id).await {
+            Some(vc) => Ok(vc),
+            None => return Ok(HttpResponse::NotFound().body("Unknown room")),
+        }
+    };
 
     let vc = match vc {
         Ok(vc) => vc,
         Err(error) => {
-            eprintln!("{error}");
+            tracing::warn!("{error}");
+
+            return Ok(HttpResponse::ServiceUnavailable().body(error));
+        }
+    };
+
+    let (metadata, options) = match query_parameters
+        .metadata()
+        .and_then(|metadata| Ok((metadata, query_parameters.options()?)))
+    {
+        Ok(parsed) => parsed,
+        Err(error) => return Ok(HttpResponse::BadRequest().body(error)),
+    };
+
+    if let Err(error) = vc.check_password(query_parameters.password.as_deref()) {
+        return Ok(HttpResponse::Forbidden().body(error));
+    }
+
+    let session_id = SessionId::next();
+    let peer_id = match vc.add_peer(
+        PeerId::from(query_parameters.user.clone()),
+        session_id,
+        metadata,
+    ) {
+        Ok(peer_id) => peer_id,
+        Err(error) => {
+            tracing::info!(vc_id = %vc.id(), "Rejected peer: {error}");
 
-            return Ok(HttpResponse::NotFound().finish());
+            return Ok(HttpResponse::Conflict().body(error));
         }
     };
 
-    match PeerConnection::new(vc, &query_parameters.user).await {
-        Ok(pc) => ws::start(pc, &request, stream),
+    match PeerConnection::new(vc.clone(), peer_id.clone(), session_id, options).await {
+        Ok(pc) => ws::WsResponseBuilder::new(pc, &request, stream)
+            .frame_size(config.messages.max_frame_size)
+            .start(),
         Err(error) => {
-            eprintln!("{error}");
+            tracing::error!(vc_id = %vc.id(), %peer_id, "{error}");
+            vc.remove_peer(&peer_id, session_id);
 
             Ok(HttpResponse::InternalServerError().finish())
         }
@@ -103,17 +237,65 @@ async fn ws_index(
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
-
-    let worker_manager = Data::new(WorkerManager::new());
-    let vc_registry = Data::new(VcRegistry::default());
-    HttpServer::new(move || {
-        App::new()
-            .app_data(worker_manager.clone())
-            .app_data(vc_registry.clone())
-            .route("/ws", web::get().to(ws_index))
+    logging::init();
+
+    let config = Arc::new(Config::from_env());
+    let worker_manager = WorkerManager::new();
+    let workers = Data::new(
+        WorkerPool::new(&worker_manager, &config.rtc)
+            .await
+            .map_err(std::io::Error::other)?,
+    );
+    let vc_registry = Data::new(VcRegistry::new(Arc::clone(&config)));
+    let drain = Data::new(Drain::default());
+    let config = Data::from(config);
+    let server = HttpServer::new({
+        let config = config.clone();
+        let workers = workers.clone();
+        let vc_registry = vc_registry.clone();
+        let drain = drain.clone();
+
+        move || {
+            App::new()
+                .app_data(config.clone())
+                .app_data(workers.clone())
+                .app_data(vc_registry.clone())
+                .app_data(drain.clone())
+                .route("/ws", web::get().to(ws_index))
+                .service(
+                    web::scope("")
+                        .wrap(config.allowed_origins.cors())
+                        .configure(health::configure)
+                        .configure(admin::configure),
+                )
+        }
     })
-    .bind("0.0.0.0:4002")?
-    .run()
-    .await
+    // Signals are handled below so that rooms get drained before the server stops
+    .disable_signals();
+    let server = match &config.tls {
+        Some(tls) => {
+            let resolver = CertResolver::new(tls.clone()).map_err(std::io::Error::other)?;
+            resolver.watch();
+            server.bind_rustls_021(&config.listen_addr, resolver.server_config())?
+        }
+        None => server.bind(&config.listen_addr)?,
+    }
+    .run();
+    let server_handle = server.handle();
+    let server = actix_web::rt::spawn(server);
+
+    shutdown::signal().await?;
+    tracing::info!("Shutting down");
+    drain.start();
+    shutdown::drain(&vc_registry, &config.drain).await;
+
+    server_handle.stop(true).await;
+    server.await.map_err(std::io::Error::other)??;
+
+    // Closes the remaining routers and then the workers
+    drop(vc_registry);
+    drop(workers);
+    tracing::info!("Shut down");
+
+    Ok(())
 }
@@ -1,6 +1,15 @@
-use crate::peer::PeerId;
+use crate::chat::{ChatEvent, ChatMessage};
+use crate::peer::{PeerDebugState, PeerId, PeerMetadata};
+use crate::playback::{PlaybackCommand, PlaybackState};
+use crate::producer::{ProducerInfo, ProducerSource};
+use crate::quality::NetworkQuality;
+use crate::settings::{RoomSettings, SettingsUpdate};
+use crate::shutdown::GoingAway;
+use crate::stats::{ConsumerScores, ProducerScores, Stats, StatsTarget};
+use crate::subscription::SubscriptionRules;
 use crate::vc::VcId;
 use actix::prelude::*;
+use actix_web_actors::ws::CloseCode;
 use mediasoup::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -13,6 +22,17 @@ pub struct TransportOptions {
     pub ice_parameters: IceParameters,
 }
 
+impl From<&WebRtcTransport> for TransportOptions {
+    fn from(transport: &WebRtcTransport) -> Self {
+        Self {
+            id: transport.id(),
+            dtls_parameters: transport.dtls_parameters(),
+            ice_candidates: transport.ice_candidates().clone(),
+            ice_parameters: transport.ice_parameters().clone(),
+        }
+    }
+}
+
 #[derive(Serialize, Message)]
 #[serde(tag = "action")]
 #[rtype(result = "()")]
@@ -20,15 +40,22 @@ pub enum S2C {
     #[serde(rename_all = "camelCase")]
     Init {
         vc_id: VcId,
+        peer_id: PeerId,
         consumer_transport_options: TransportOptions,
-        producer_transport_options: TransportOptions,
+        /// Absent for spectators and lazily created transports, see
+        /// [`C2S::CreateProducerTransport`].
+        producer_transport_options: Option<TransportOptions>,
         router_rtp_capabilities: RtpCapabilitiesFinalized,
+        settings: Box<RoomSettings>,
     },
 
     #[serde(rename_all = "camelCase")]
     ProducerAdd {
         peer_id: PeerId,
         producer_id: ProducerId,
+        kind: MediaKind,
+        #[serde(flatten)]
+        info: ProducerInfo,
     },
 
     #[serde(rename_all = "camelCase")]
@@ -37,10 +64,20 @@ pub enum S2C {
         producer_id: ProducerId,
     },
 
-    #[serde(rename_all = "camelCase")]
-    Echo {
-        peer_id: PeerId,
-        text: String,
+    Chat(ChatEvent),
+
+    ChatHistory {
+        messages: Vec<ChatMessage>,
+    },
+
+    /// Confirms a [`C2S::ChatSend`], echoing the client supplied nonce.
+    ChatAck {
+        nonce: Option<String>,
+        id: u64,
+    },
+
+    ProducerTransportCreated {
+        options: TransportOptions,
     },
 
     ConnectedProducerTransport,
@@ -60,7 +97,70 @@ pub enum S2C {
         rtp_parameters: RtpParameters,
     },
 
-    Notification(Notification),
+    /// The server paused or resumed a consumer because of subscription rules or last-N.
+    ConsumerState {
+        id: ConsumerId,
+        paused: bool,
+    },
+
+    Notification(StampedNotification),
+
+    #[serde(rename_all = "camelCase")]
+    TimeSync {
+        client_send_time: f64,
+        server_receive_time: u64,
+        server_send_time: u64,
+    },
+
+    Playback(PlaybackState),
+
+    /// The host changed the room's settings.
+    Settings(RoomSettings),
+
+    /// Answers [`C2S::GetStats`].
+    Stats {
+        target: StatsTarget,
+        stats: Stats,
+    },
+
+    /// The peer's own network quality changed. While poor, video consumers are paused and while
+    /// fair, only their lowest spatial layer is forwarded.
+    NetworkQuality {
+        quality: NetworkQuality,
+    },
+
+    /// Periodic connection quality of the peer's own producers and consumers.
+    Scores {
+        producers: Vec<ProducerScores>,
+        consumers: Vec<ConsumerScores>,
+    },
+
+    HandQueue {
+        peers: Vec<PeerId>,
+    },
+
+    /// The server is shutting down, the client should reconnect after the given delay.
+    GoingAway(GoingAway),
+
+    /// The room was closed and the connection is about to be closed as well.
+    RoomClosed {
+        reason: String,
+    },
+
+    Error {
+        message: String,
+    },
+}
+
+impl S2C {
+    pub fn producer_add(peer_id: PeerId, producer: &Producer) -> Self {
+        S2C::ProducerAdd {
+            peer_id,
+            producer_id: producer.id(),
+            kind: producer.kind(),
+            info: ProducerInfo::of(producer),
+        }
+    }
 }
 
 #[derive(Deserialize, Message)]
@@ -72,6 +172,9 @@ pub enum C2S {
         rtp_capabilities: RtpCapabilities,
     },
 
+    /// Answered with [`S2C::ProducerTransportCreated`].
+    CreateProducerTransport,
+
     #[serde(rename_all = "camelCase")]
     ConnectProducerTransport {
         dtls_parameters: DtlsParameters,
@@ -81,6 +184,11 @@ pub enum C2S {
     Produce {
         kind: MediaKind,
         rtp_parameters: RtpParameters,
+        /// Defaults to microphone for audio and camera for video.
+        #[serde(default)]
+        source: Option<ProducerSource>,
+        #[serde(default)]
+        app_data: serde_json::Value,
     },
 
     #[serde(rename_all = "camelCase")]
@@ -103,59 +211,271 @@ pub enum C2S {
         id: ConsumerId,
     },
 
-    Echo {
+    /// Replaces the peer's subscription rules.
+    Subscribe {
+        rules: SubscriptionRules,
+    },
+
+    ChatSend {
         text: String,
+        #[serde(default)]
+        nonce: Option<String>,
+    },
+
+    ChatEdit {
+        id: u64,
+        text: String,
+    },
+
+    ChatDelete {
+        id: u64,
     },
 
     Notification {
         kind: NotificationType,
     },
+
+    /// Host only, changes the room's settings.
+    UpdateSettings {
+        settings: SettingsUpdate,
+    },
+
+    /// Replaces the peer's metadata.
+    UpdateMetadata {
+        metadata: PeerMetadata,
+    },
+
+    /// Ephemeral signal such as an emoji reaction, `signal` must be a registered type.
+    Signal {
+        signal: String,
+        #[serde(default)]
+        payload: Option<serde_json::Value>,
+    },
+
+    RaiseHand,
+
+    /// Lowers the own hand, or `peerId`'s when sent by the host.
+    #[serde(rename_all = "camelCase")]
+    LowerHand {
+        #[serde(default)]
+        peer_id: Option<PeerId>,
+    },
+
+    /// Host only.
+    ClearHands,
+
+    /// Host only, answered with [`S2C::HandQueue`].
+    GetHands,
+
+    /// NTP style clock probe, answered with [`S2C::TimeSync`]. `client_send_time` is echoed back
+    /// untouched and may use any client clock.
+    #[serde(rename_all = "camelCase")]
+    TimeSync {
+        client_send_time: f64,
+    },
+
+    /// Host only, see [`crate::vc::Vc::playback`].
+    Playback {
+        command: PlaybackCommand,
+    },
+
+    /// Stats of one of the peer's own producers, consumers or transports, answered with
+    /// [`S2C::Stats`].
+    GetStats {
+        target: StatsTarget,
+    },
+}
+
+impl C2S {
+    /// The `action` tag the message was sent with.
+    pub fn action(&self) -> &'static str {
+        match self {
+            C2S::Init { .. } => "Init",
+            C2S::CreateProducerTransport => "CreateProducerTransport",
+            C2S::ConnectProducerTransport { .. } => "ConnectProducerTransport",
+            C2S::Produce { .. } => "Produce",
+            C2S::ProducerRemove { .. } => "ProducerRemove",
+            C2S::ConnectConsumerTransport { .. } => "ConnectConsumerTransport",
+            C2S::Consume { .. } => "Consume",
+            C2S::ConsumerResume { .. } => "ConsumerResume",
+            C2S::Subscribe { .. } => "Subscribe",
+            C2S::ChatSend { .. } => "ChatSend",
+            C2S::ChatEdit { .. } => "ChatEdit",
+            C2S::ChatDelete { .. } => "ChatDelete",
+            C2S::Notification { .. } => "Notification",
+            C2S::UpdateSettings { .. } => "UpdateSettings",
+            C2S::UpdateMetadata { .. } => "UpdateMetadata",
+            C2S::Signal { .. } => "Signal",
+            C2S::RaiseHand => "RaiseHand",
+            C2S::LowerHand { .. } => "LowerHand",
+            C2S::ClearHands => "ClearHands",
+            C2S::GetHands => "GetHands",
+            C2S::TimeSync { .. } => "TimeSync",
+            C2S::Playback { .. } => "Playback",
+            C2S::GetStats { .. } => "GetStats",
+        }
+    }
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub enum InternalMessage {
+    SaveProducerTransport(WebRtcTransport),
+
     SaveProducer(Producer),
 
-    SaveConsumer(Consumer),
+    /// A created consumer, `resumed` when it should run without waiting for
+    /// [`C2S::ConsumerResume`].
+    SaveConsumer {
+        consumer: Consumer,
+        resumed: bool,
+    },
+
+    ConsumerTransportConnected,
+
+    /// A producer matching the auto-subscribe policy was added to the room.
+    AutoConsume(ProducerId),
+
+    /// The producer of a consumer closed.
+    ConsumerClosed(ConsumerId),
+
+    SpeakersChange(Vec<PeerId>),
+
+    /// The room's spatial layer cap changed, see [`crate::vc::Vc::layer_cap`].
+    LayerCap(Option<u8>),
 
     Stop,
+
+    /// Closes the WebSocket with the given code and reason.
+    Close(CloseCode, String),
 }
 
-#[derive(Deserialize)]
+/// Asks a connection for its state, see [`crate::vc::Vc::debug_dump`].
+#[derive(Message)]
+#[rtype(result = "PeerDebugState")]
+pub struct DebugState;
+
+#[derive(Clone, Copy, Deserialize)]
 pub enum NotificationType {
     Loading,
     Playing,
     Idle,
 }
 
+impl NotificationType {
+    pub fn notification(self, peer_id: PeerId) -> Notification {
+        match self {
+            NotificationType::Loading => Notification::Loading { peer_id },
+            NotificationType::Playing => Notification::Playing { peer_id },
+            NotificationType::Idle => Notification::Idle { peer_id },
+        }
+    }
+}
+
 #[derive(Clone, Serialize)]
 #[serde(tag = "kind")]
 pub enum Notification {
     #[serde(rename_all = "camelCase")]
-    PeerJoin { peer_id: PeerId },
+    PeerJoin {
+        peer_id: PeerId,
+        metadata: PeerMetadata,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    PeerUpdate {
+        peer_id: PeerId,
+        metadata: PeerMetadata,
+    },
 
     #[serde(rename_all = "camelCase")]
-    PeerLeave { peer_id: PeerId },
+    PeerLeave {
+        peer_id: PeerId,
+    },
 
     #[serde(rename_all = "camelCase")]
-    Loading { peer_id: PeerId },
+    Loading {
+        peer_id: PeerId,
+    },
 
     #[serde(rename_all = "camelCase")]
-    Playing { peer_id: PeerId },
+    Playing {
+        peer_id: PeerId,
+    },
 
     #[serde(rename_all = "camelCase")]
-    Idle { peer_id: PeerId },
+    Idle {
+        peer_id: PeerId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    HostChange {
+        peer_id: PeerId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    Signal {
+        peer_id: PeerId,
+        signal: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        payload: Option<serde_json::Value>,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    HandRaise {
+        peer_id: PeerId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    HandLower {
+        peer_id: PeerId,
+    },
+
+    HandsClear,
+
+    #[serde(rename_all = "camelCase")]
+    DominantSpeaker {
+        peer_id: PeerId,
+    },
+
+    #[serde(rename_all = "camelCase")]
+    NetworkQuality {
+        peer_id: PeerId,
+        quality: NetworkQuality,
+    },
+}
+
+/// A [`Notification`] with the server time (ms since the Unix epoch) it was emitted at.
+#[derive(Clone, Serialize)]
+pub struct StampedNotification {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub notification: Notification,
 }
 
 impl Notification {
+    pub fn stamp(self, timestamp: u64) -> StampedNotification {
+        StampedNotification {
+            timestamp,
+            notification: self,
+        }
+    }
+
     pub fn associated_peer_id(&self) -> Option<&PeerId> {
         match self {
-            Notification::PeerJoin { peer_id } => Some(peer_id),
+            Notification::PeerJoin { peer_id, .. } => Some(peer_id),
+            Notification::PeerUpdate { peer_id, .. } => Some(peer_id),
             Notification::PeerLeave { peer_id } => Some(peer_id),
             Notification::Loading { peer_id } => Some(peer_id),
             Notification::Playing { peer_id } => Some(peer_id),
             Notification::Idle { peer_id } => Some(peer_id),
+            Notification::Signal { peer_id, .. } => Some(peer_id),
+            Notification::DominantSpeaker { peer_id } => Some(peer_id),
+            Notification::NetworkQuality { peer_id, .. } => Some(peer_id),
+            // Room state changes that concern the associated peer too
+            Notification::HostChange { .. }
+            | Notification::HandRaise { .. }
+            | Notification::HandLower { .. }
+            | Notification::HandsClear => None,
         }
     }
 }
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+use actix_cors::Cors;
+use actix_web::http::{header, Method};
+use actix_web::HttpRequest;
+
+/// Origins browsers may connect from, parsed from a comma separated list such as
+/// `"https://app.example.com,https://staging.example.com"`. `*` allows any origin.
+#[derive(Debug, Clone, Default)]
+pub enum AllowedOrigins {
+    #[default]
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    pub fn allows(&self, origin: &str) -> bool {
+        match self {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(origins) => origins.iter().any(|allowed| allowed == origin),
+        }
+    }
+
+    /// Checks the `Origin` of a WebSocket upgrade. Requests without one do not come from a
+    /// browser and are let through, the check only keeps other sites' pages from connecting.
+    pub fn check(&self, request: &HttpRequest) -> Result<(), String> {
+        let Some(origin) = request.headers().get(header::ORIGIN) else {
+            return Ok(());
+        };
+
+        match origin.to_str() {
+            Ok(origin) if self.allows(origin) => Ok(()),
+            _ => Err(format!("Origin {origin:?} is not allowed")),
+        }
+    }
+
+    /// CORS for the HTTP endpoints.
+    pub fn cors(&self) -> Cors {
+        let cors = Cors::default()
+            .allowed_methods([Method::GET, Method::POST, Method::DELETE])
+            .allowed_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .max_age(3600);
+
+        match self {
+            AllowedOrigins::Any => cors.allow_any_origin(),
+            AllowedOrigins::List(origins) => origins
+                .iter()
+                .fold(cors, |cors, origin| cors.allowed_origin(origin)),
+        }
+    }
+}
+
+impl FromStr for AllowedOrigins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let origins = s
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| origin.trim_end_matches('/').to_string())
+            .collect::<Vec<_>>();
+        if origins.iter().any(|origin| origin == "*") {
+            return Ok(AllowedOrigins::Any);
+        }
+        if origins.is_empty() {
+            return Err("Expected a list of origins or *".to_string());
+        }
+
+        Ok(AllowedOrigins::List(origins))
+    }
+}
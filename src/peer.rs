@@ -1,25 +1,264 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use actix::{Actor, ActorContext, AsyncContext, Handler, StreamHandler};
+use actix::{Actor, ActorContext, AsyncContext, Handler, MessageResult, StreamHandler};
 use actix_web_actors::ws;
 use event_listener_primitives::HandlerId;
+use mediasoup::consumer::ConsumerType;
 use mediasoup::prelude::*;
 use serde::{Deserialize, Serialize};
+use tracing::{Instrument, Span};
 
-use crate::{message::*, vc::Vc};
+use crate::{
+    bandwidth::MAX_SPATIAL_LAYER,
+    clock,
+    message::*,
+    producer::{ProducerInfo, ProducerSource},
+    quality::{NetworkQuality, QualityTracker},
+    ratelimit::{MessageLimiter, Verdict},
+    stats::{ConsumerScores, ProducerScores, StatsSource, StatsTarget},
+    subscription::{self, SubscriptionRules},
+    vc::Vc,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Ord, PartialOrd, Deserialize, Serialize)]
 pub struct PeerId(String);
 
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for PeerId {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl PeerId {
+    pub fn with_session(&self, session_id: SessionId) -> Self {
+        Self(format!("{}#{}", self.0, session_id.0))
+    }
+}
+
+/// Descriptive data a peer supplies at join and may update later on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerMetadata {
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub client_info: Option<String>,
+    /// Arbitrary application specific JSON.
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub app_data: serde_json::Value,
+}
+
+impl PeerMetadata {
+    const MAX_FIELD_LENGTH: usize = 256;
+    const MAX_APP_DATA_SIZE: usize = 4096;
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, field) in [
+            ("displayName", &self.display_name),
+            ("avatarUrl", &self.avatar_url),
+            ("clientInfo", &self.client_info),
+        ] {
+            if field
+                .as_ref()
+                .is_some_and(|field| field.chars().count() > Self::MAX_FIELD_LENGTH)
+            {
+                return Err(format!(
+                    "{name} is longer than {} characters",
+                    Self::MAX_FIELD_LENGTH
+                ));
+            }
+        }
+        if self.app_data.to_string().len() > Self::MAX_APP_DATA_SIZE {
+            return Err(format!(
+                "appData is larger than {} bytes",
+                Self::MAX_APP_DATA_SIZE
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies a single WebSocket connection, distinguishing sessions that share a [`PeerId`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct SessionId(u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl SessionId {
+    pub fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Which producers the server consumes on a peer's behalf, without waiting for `C2S::Consume`.
+/// Such consumers start resumed, the client does not need to send `C2S::ConsumerResume`. Parsed
+/// from `off`, `all` or a comma separated list of producer sources.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AutoSubscribe {
+    #[default]
+    Off,
+    All,
+    Sources(HashSet<ProducerSource>),
+}
+
+impl AutoSubscribe {
+    pub fn matches(&self, info: &ProducerInfo) -> bool {
+        match self {
+            AutoSubscribe::Off => false,
+            AutoSubscribe::All => true,
+            AutoSubscribe::Sources(sources) => sources.contains(&info.source),
+        }
+    }
+}
+
+impl FromStr for AutoSubscribe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" | "off" => Ok(Self::Off),
+            "all" => Ok(Self::All),
+            sources => sources
+                .split(',')
+                .map(|source| source.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(Self::Sources),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    #[default]
+    Participant,
+    /// Receive only, never gets a producer transport.
+    Spectator,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "participant" => Ok(Self::Participant),
+            "spectator" => Ok(Self::Spectator),
+            _ => Err(format!("Unknown role {s:?}")),
+        }
+    }
+}
+
+/// Per connection options chosen by the client at join.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerOptions {
+    pub role: Role,
+    pub auto_subscribe: AutoSubscribe,
+    /// Defer the producer transport until [`C2S::CreateProducerTransport`].
+    pub lazy_producer_transport: bool,
+}
+
+/// Connection state of a peer as included in the admin debug dump.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDebugState {
+    session_id: SessionId,
+    options: PeerOptions,
+    rtp_capabilities_received: bool,
+    consumer_transport_id: TransportId,
+    consumer_transport_connected: bool,
+    producer_transport_id: Option<TransportId>,
+    producer_transport_pending: bool,
+    producers: Vec<ProducerId>,
+    consumers: Vec<ConsumerDebugState>,
+    auto_subscribed: Option<HashSet<ProducerId>>,
+    subscription: SubscriptionRules,
+    layer_cap: Option<u8>,
+    effective_layer_cap: Option<u8>,
+    uplink: NetworkQuality,
+    downlink: NetworkQuality,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConsumerDebugState {
+    id: ConsumerId,
+    producer_id: ProducerId,
+    peer_id: PeerId,
+    source: ProducerSource,
+    resumed: bool,
+    paused: bool,
+}
+
+struct ConsumerEntry {
+    consumer: Consumer,
+    /// Owner of the consumed producer.
+    peer_id: PeerId,
+    source: ProducerSource,
+    /// Whether the client asked for the consumer to run, the server may still hold it paused.
+    resumed: bool,
+    _producer_close_handler: HandlerId,
+}
+
+impl ConsumerEntry {
+    /// Whether the consumer competes for a last-N slot. Screen shares and shared media are
+    /// watched regardless of who spoke last.
+    fn in_last_n(&self) -> bool {
+        self.consumer.kind() == MediaKind::Video
+            && !matches!(self.source, ProducerSource::Screen | ProducerSource::Media)
+    }
+}
+
 struct Transports {
     consumer: WebRtcTransport,
-    producer: WebRtcTransport,
+    /// Only created once the peer wants to publish, never for spectators.
+    producer: Option<WebRtcTransport>,
+    producer_pending: bool,
 }
 
 pub struct PeerConnection {
     id: PeerId,
+    /// Child of the room's span, carries `peer_id` and `session_id`.
+    span: Span,
+    session_id: SessionId,
+    options: PeerOptions,
     client_rtp_capabilities: Option<RtpCapabilities>,
-    consumers: HashMap<ConsumerId, Consumer>,
+    consumer_transport_connected: bool,
+    /// Producers consumed through auto-subscribe, guards against consuming one twice.
+    auto_subscribed: Option<HashSet<ProducerId>>,
+    consumers: HashMap<ConsumerId, ConsumerEntry>,
+    /// Consumers being created, counted towards the consumer limit.
+    consumers_pending: usize,
+    subscription: SubscriptionRules,
+    /// Mirror of [`Vc::speakers`], drives last-N forwarding.
+    speakers: Vec<PeerId>,
+    /// Mirror of [`Vc::layer_cap`], applied to simulcast and SVC consumers.
+    layer_cap: Option<u8>,
+    /// Scores of what the peer publishes.
+    uplink: QualityTracker,
+    /// Scores of what the peer receives, degrades its consumers when bad.
+    downlink: QualityTracker,
+    /// Worse of the uplink and downlink quality, as announced to the room.
+    quality: NetworkQuality,
+    /// Rate limits on what the client sends.
+    limiter: MessageLimiter,
     producers: Vec<Producer>,
     transports: Transports,
     vc: Vc,
@@ -28,126 +267,684 @@ pub struct PeerConnection {
 
 impl Drop for PeerConnection {
     fn drop(&mut self) {
-        self.vc.remove_peer(&self.id);
+        self.vc.remove_peer(&self.id, self.session_id);
     }
 }
 
 impl PeerConnection {
-    pub async fn new(vc: Vc, peer_id: impl Into<String>) -> Result<Self, String> {
-        let transport_options =
-            WebRtcTransportOptions::new(WebRtcTransportListenInfos::new(ListenInfo {
-                protocol: Protocol::Udp,
-                ip: std::env::var("IP")
-                    .expect("IP environment variable not set")
-                    .parse()
-                    .expect("Invalid ip"),
-                port: None,
-                announced_ip: std::env::var("ANNOUNCED_IP")
-                    .ok()
-                    .map(|x| x.parse().expect("Invalid announced ip")),
-                send_buffer_size: None,
-                recv_buffer_size: None,
-            }));
-        let producer_transport = vc
-            .router()
-            .create_webrtc_transport(transport_options.clone())
-            .await
-            .map_err(|error| format!("Failed to create producer transport: {error}"))?;
+    /// Rate limit action of client frames that are not a valid [`C2S`] message.
+    const INVALID_ACTION: &'static str = "Invalid";
+    /// Characters of an invalid client message that make it into the log.
+    const MAX_LOGGED_TEXT: usize = 256;
 
-        let consumer_transport = vc
-            .router()
-            .create_webrtc_transport(transport_options)
+    /// Creates the connection for a peer already registered through [`Vc::add_peer`].
+    pub async fn new(
+        vc: Vc,
+        peer_id: PeerId,
+        session_id: SessionId,
+        options: PeerOptions,
+    ) -> Result<Self, String> {
+        let limits = vc.config().bandwidth.limits(options.role);
+        let producer_transport =
+            if options.role == Role::Participant && !options.lazy_producer_transport {
+                Some(
+                    create_transport(&vc, limits.max_incoming, None)
+                        .await
+                        .map_err(|error| format!("Failed to create producer transport: {error}"))?,
+                )
+            } else {
+                None
+            };
+
+        let consumer_transport = create_transport(&vc, None, limits.max_outgoing)
             .await
             .map_err(|error| format!("Failed to create consumer transport: {error}"))?;
 
+        for transport in producer_transport.iter().chain([&consumer_transport]) {
+            vc.add_transport(&peer_id, transport);
+        }
+
         Ok(Self {
-            id: PeerId(peer_id.into()),
+            span: tracing::info_span!(
+                parent: vc.span(),
+                "peer",
+                %peer_id,
+                %session_id,
+            ),
+            id: peer_id,
+            session_id,
+            options,
             client_rtp_capabilities: None,
+            consumer_transport_connected: false,
+            auto_subscribed: None,
             consumers: HashMap::new(),
+            consumers_pending: 0,
+            subscription: SubscriptionRules::default(),
+            speakers: vc.speakers(),
+            layer_cap: vc.layer_cap(),
+            uplink: QualityTracker::new(vc.config().quality.window),
+            downlink: QualityTracker::new(vc.config().quality.window),
+            quality: NetworkQuality::Good,
+            limiter: MessageLimiter::new(&vc.config().messages),
             producers: vec![],
             transports: Transports {
                 consumer: consumer_transport,
                 producer: producer_transport,
+                producer_pending: false,
             },
             vc,
             attached_handlers: Vec::new(),
         })
     }
+
+    /// Spawns `future` within the peer's span.
+    fn spawn<F: Future<Output = ()> + 'static>(&self, future: F) {
+        actix::spawn(future.instrument(self.span.clone()));
+    }
+
+    fn can_publish(&self) -> Result<(), String> {
+        match self.options.role {
+            Role::Participant => self.vc.can_publish(&self.id),
+            Role::Spectator => Err("Spectators cannot publish".to_string()),
+        }
+    }
+
+    fn producer_transport(&self) -> Result<WebRtcTransport, String> {
+        self.can_publish()?;
+        self.transports
+            .producer
+            .clone()
+            .ok_or_else(|| "Producer transport not created yet".to_string())
+    }
+
+    /// Creates a consumer paused on the server side, which runs right away if `resumed` and
+    /// otherwise once the client sends [`C2S::ConsumerResume`].
+    fn consume(
+        &mut self,
+        producer_id: ProducerId,
+        resumed: bool,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let address = ctx.address();
+        if let Some(limit) = self.vc.config().capacity.max_consumers_per_peer {
+            if self.consumers.len() + self.consumers_pending >= limit {
+                address.do_send(S2C::Error {
+                    message: format!("A peer may have at most {limit} consumers"),
+                });
+                return;
+            }
+        }
+        let transport = self.transports.consumer.clone();
+        let layer_cap = self.effective_layer_cap();
+        let rtp_capabilities = match self.client_rtp_capabilities.clone() {
+            Some(rtp_capabilities) => rtp_capabilities,
+            None => {
+                tracing::warn!("Client should send RTP capabilities before consuming");
+                return;
+            }
+        };
+        self.consumers_pending += 1;
+        self.spawn(async move {
+            let mut options = ConsumerOptions::new(producer_id, rtp_capabilities);
+            options.paused = true;
+            options.preferred_layers = layer_cap.map(|spatial_layer| ConsumerLayers {
+                spatial_layer,
+                temporal_layer: None,
+            });
+
+            match transport.consume(options).await {
+                Ok(consumer) => {
+                    let id = consumer.id();
+                    let kind = consumer.kind();
+                    let rtp_parameters = consumer.rtp_parameters().clone();
+                    address.do_send(S2C::ConsumerCreated {
+                        id,
+                        producer_id,
+                        kind,
+                        rtp_parameters,
+                    });
+                    address.do_send(InternalMessage::SaveConsumer { consumer, resumed });
+                    tracing::debug!(consumer_id = %id, ?kind, "Consumer created");
+                }
+                Err(error) => {
+                    tracing::error!(%producer_id, "Failed to create consumer: {error}");
+                    address.do_send(InternalMessage::Stop);
+                }
+            }
+        });
+    }
+
+    /// Consumes every matching producer in the room once the client's RTP capabilities are known
+    /// and the consumer transport exists, later producers are picked up through
+    /// [`InternalMessage::AutoConsume`].
+    fn start_auto_subscribe(&mut self, ctx: &mut <Self as Actor>::Context) {
+        if matches!(self.options.auto_subscribe, AutoSubscribe::Off)
+            || self.auto_subscribed.is_some()
+            || self.client_rtp_capabilities.is_none()
+        {
+            return;
+        }
+
+        self.auto_subscribed = Some(HashSet::new());
+        for (peer_id, producer) in self.vc.get_all_producers() {
+            if peer_id != self.id
+                && self
+                    .options
+                    .auto_subscribe
+                    .matches(&ProducerInfo::of(&producer))
+            {
+                self.auto_consume(producer.id(), ctx);
+            }
+        }
+    }
+
+    fn auto_consume(&mut self, producer_id: ProducerId, ctx: &mut <Self as Actor>::Context) {
+        let Some(subscribed) = &mut self.auto_subscribed else {
+            // Not ready yet, `start_auto_subscribe` will pick this producer up
+            return;
+        };
+        let Some(rtp_capabilities) = &self.client_rtp_capabilities else {
+            return;
+        };
+        if !self.vc.router().can_consume(&producer_id, rtp_capabilities)
+            || !subscribed.insert(producer_id)
+        {
+            return;
+        }
+
+        self.consume(producer_id, true, ctx);
+    }
+
+    /// Pauses or resumes consumers so that exactly those resumed by the client, allowed by the
+    /// subscription rules and, for camera video, within the room's last-N are forwarded.
+    fn update_consumers(&self, ctx: &mut <Self as Actor>::Context) {
+        let last_n = self.vc.last_n().map(|n| {
+            let candidates = self
+                .consumers
+                .values()
+                .filter(|entry| entry.in_last_n())
+                .map(|entry| &entry.peer_id)
+                .collect();
+            subscription::last_n(&self.speakers, &candidates, n)
+        });
+
+        for entry in self.consumers.values() {
+            let kind = entry.consumer.kind();
+            let allowed = self.subscription.allows(&entry.peer_id, kind, entry.source)
+                && (kind == MediaKind::Audio
+                    || (self.downlink.quality() != NetworkQuality::Poor
+                        && (!entry.in_last_n()
+                            || last_n
+                                .as_ref()
+                                .is_none_or(|last_n| last_n.contains(&entry.peer_id)))));
+            let paused = !(entry.resumed && allowed);
+            if paused == entry.consumer.paused() {
+                continue;
+            }
+
+            let consumer = entry.consumer.clone();
+            let address = ctx.address();
+            // Only tell the client about pauses it did not ask for itself
+            let notify = entry.resumed;
+            self.spawn(async move {
+                let result = if paused {
+                    consumer.pause().await
+                } else {
+                    consumer.resume().await
+                };
+                match result {
+                    Ok(_) => {
+                        tracing::debug!(
+                            consumer_id = %consumer.id(),
+                            kind = ?consumer.kind(),
+                            paused,
+                            "Consumer state changed"
+                        );
+                        if notify {
+                            address.do_send(S2C::ConsumerState {
+                                id: consumer.id(),
+                                paused,
+                            });
+                        }
+                    }
+                    Err(error) => {
+                        tracing::error!(
+                            consumer_id = %consumer.id(),
+                            paused,
+                            "Failed to change consumer state: {error}"
+                        );
+                    }
+                }
+            });
+        }
+    }
+
+    /// The room's layer cap, lowered to the bottom layer while the peer's downlink is fair.
+    fn effective_layer_cap(&self) -> Option<u8> {
+        if self.downlink.quality() == NetworkQuality::Fair {
+            Some(0)
+        } else {
+            self.layer_cap
+        }
+    }
+
+    /// Caps the spatial layer of every layered consumer to the effective layer cap.
+    fn apply_layer_cap(&self) {
+        let layers = ConsumerLayers {
+            spatial_layer: self.effective_layer_cap().unwrap_or(MAX_SPATIAL_LAYER),
+            temporal_layer: None,
+        };
+
+        for entry in self.consumers.values() {
+            if !matches!(
+                entry.consumer.r#type(),
+                ConsumerType::Simulcast | ConsumerType::Svc
+            ) {
+                continue;
+            }
+
+            let consumer = entry.consumer.clone();
+            self.spawn(async move {
+                if let Err(error) = consumer.set_preferred_layers(layers).await {
+                    tracing::error!(
+                        consumer_id = %consumer.id(),
+                        "Failed to set preferred layers: {error}"
+                    );
+                }
+            });
+        }
+    }
+
+    /// Reports the consumer transport's send bitrate towards the room budget.
+    fn report_outgoing_bitrate(&self) {
+        let transport = self.transports.consumer.clone();
+        let session_id = self.session_id;
+        let vc = self.vc.clone();
+        self.spawn(async move {
+            match transport.get_stats().await {
+                Ok(stats) => {
+                    let bitrate = stats.iter().map(|stat| stat.send_bitrate).sum();
+                    vc.report_outgoing_bitrate(session_id, bitrate);
+                }
+                Err(error) => {
+                    tracing::warn!("Failed to get consumer transport stats: {error}");
+                }
+            }
+        });
+    }
+
+    /// Resolves `target` among the peer's own objects.
+    fn stats_source(&self, target: &StatsTarget) -> Option<StatsSource> {
+        match *target {
+            StatsTarget::Producer { id } => self
+                .producers
+                .iter()
+                .find(|producer| producer.id() == id)
+                .cloned()
+                .map(StatsSource::Producer),
+            StatsTarget::Consumer { id } => self
+                .consumers
+                .get(&id)
+                .map(|entry| StatsSource::Consumer(entry.consumer.clone())),
+            StatsTarget::Transport { id } => self
+                .transports
+                .producer
+                .iter()
+                .chain([&self.transports.consumer])
+                .find(|transport| transport.id() == id)
+                .cloned()
+                .map(StatsSource::Transport),
+        }
+    }
+
+    fn send_scores(&self, ctx: &mut <Self as Actor>::Context) {
+        if self.producers.is_empty() && self.consumers.is_empty() {
+            return;
+        }
+
+        ctx.address().do_send(S2C::Scores {
+            producers: self
+                .producers
+                .iter()
+                .map(|producer| ProducerScores {
+                    id: producer.id(),
+                    score: producer.score(),
+                })
+                .collect(),
+            consumers: self
+                .consumers
+                .values()
+                .map(|entry| ConsumerScores {
+                    id: entry.consumer.id(),
+                    score: entry.consumer.score(),
+                })
+                .collect(),
+        });
+    }
+
+    /// Feeds the current producer and consumer scores into the quality trackers, announcing
+    /// quality changes and degrading or restoring consumers as the downlink changes.
+    fn sample_quality(&mut self, ctx: &mut <Self as Actor>::Context) {
+        let uplink = self
+            .producers
+            .iter()
+            .flat_map(|producer| producer.score())
+            .map(|score| score.score)
+            .min();
+        if let Some(score) = uplink {
+            self.uplink.sample(score);
+        }
+
+        // Paused consumers carry no traffic to judge. Audio is never paused for a poor downlink,
+        // so it tells when video may come back, without it the tracker probes on its own.
+        let downlink = self
+            .consumers
+            .values()
+            .filter(|entry| !entry.consumer.paused())
+            .map(|entry| entry.consumer.score().score)
+            .min();
+        let changed = match downlink {
+            Some(score) => self.downlink.sample(score),
+            None => self.downlink.idle(),
+        };
+        if changed.is_some() {
+            self.update_consumers(ctx);
+            self.apply_layer_cap();
+        }
+
+        let quality = self.uplink.quality().min(self.downlink.quality());
+        if quality != self.quality {
+            self.quality = quality;
+            tracing::info!(?quality, "Network quality changed");
+            self.vc.set_network_quality(&self.id, quality);
+            ctx.address().do_send(S2C::NetworkQuality { quality });
+        }
+    }
+
+    fn debug_state(&self) -> PeerDebugState {
+        PeerDebugState {
+            session_id: self.session_id,
+            options: self.options.clone(),
+            rtp_capabilities_received: self.client_rtp_capabilities.is_some(),
+            consumer_transport_id: self.transports.consumer.id(),
+            consumer_transport_connected: self.consumer_transport_connected,
+            producer_transport_id: self
+                .transports
+                .producer
+                .as_ref()
+                .map(|transport| transport.id()),
+            producer_transport_pending: self.transports.producer_pending,
+            producers: self.producers.iter().map(Producer::id).collect(),
+            consumers: self
+                .consumers
+                .values()
+                .map(|entry| ConsumerDebugState {
+                    id: entry.consumer.id(),
+                    producer_id: entry.consumer.producer_id(),
+                    peer_id: entry.peer_id.clone(),
+                    source: entry.source,
+                    resumed: entry.resumed,
+                    paused: entry.consumer.paused(),
+                })
+                .collect(),
+            auto_subscribed: self.auto_subscribed.clone(),
+            subscription: self.subscription.clone(),
+            layer_cap: self.layer_cap,
+            effective_layer_cap: self.effective_layer_cap(),
+            uplink: self.uplink.quality(),
+            downlink: self.downlink.quality(),
+        }
+    }
+
+    fn time_sync(
+        &self,
+        client_send_time: f64,
+        server_receive_time: u64,
+        ctx: &mut <Self as Actor>::Context,
+    ) {
+        let message = S2C::TimeSync {
+            client_send_time,
+            server_receive_time,
+            server_send_time: clock::now_millis(),
+        };
+        ctx.text(serde_json::to_string(&message).unwrap());
+    }
+
+    /// Applies the message rate limits, returns whether the message should be handled.
+    fn check_rate_limit(
+        &mut self,
+        action: &'static str,
+        ctx: &mut <Self as Actor>::Context,
+    ) -> bool {
+        match self.limiter.check(action) {
+            Verdict::Allow => true,
+            Verdict::Warn => {
+                tracing::warn!(action, "Client message over the rate limit");
+                ctx.address().do_send(S2C::Error {
+                    message: format!("Too many {action} messages, slow down"),
+                });
+                true
+            }
+            Verdict::Drop => {
+                tracing::warn!(action, "Dropped client message over the rate limit");
+                false
+            }
+            Verdict::Disconnect => {
+                tracing::warn!(action, "Disconnecting client exceeding rate limits");
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("Rate limit exceeded".to_string()),
+                }));
+                ctx.stop();
+                false
+            }
+        }
+    }
+}
+
+/// Creates a transport through the room's transport options with the given bitrate caps in bps.
+async fn create_transport(
+    vc: &Vc,
+    max_incoming: Option<u32>,
+    max_outgoing: Option<u32>,
+) -> Result<WebRtcTransport, String> {
+    let transport = vc
+        .router()
+        .create_webrtc_transport(vc.webrtc_transport_options())
+        .await
+        .map_err(|error| error.to_string())?;
+
+    if let Some(bitrate) = max_incoming {
+        transport
+            .set_max_incoming_bitrate(bitrate)
+            .await
+            .map_err(|error| format!("Failed to set max incoming bitrate: {error}"))?;
+    }
+    if let Some(bitrate) = max_outgoing {
+        transport
+            .set_max_outgoing_bitrate(bitrate)
+            .await
+            .map_err(|error| format!("Failed to set max outgoing bitrate: {error}"))?;
+    }
+
+    Ok(transport)
 }
 
 impl Actor for PeerConnection {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        let _entered = self.span.clone().entered();
+        tracing::info!(role = ?self.options.role, "WebSocket connection started");
+
         let server_init_message = S2C::Init {
             vc_id: self.vc.id(),
-            consumer_transport_options: TransportOptions {
-                id: self.transports.consumer.id(),
-                dtls_parameters: self.transports.consumer.dtls_parameters(),
-                ice_candidates: self.transports.consumer.ice_candidates().clone(),
-                ice_parameters: self.transports.consumer.ice_parameters().clone(),
-            },
-            producer_transport_options: TransportOptions {
-                id: self.transports.producer.id(),
-                dtls_parameters: self.transports.producer.dtls_parameters(),
-                ice_candidates: self.transports.producer.ice_candidates().clone(),
-                ice_parameters: self.transports.producer.ice_parameters().clone(),
-            },
+            peer_id: self.id.clone(),
+            consumer_transport_options: TransportOptions::from(&self.transports.consumer),
+            producer_transport_options: self
+                .transports
+                .producer
+                .as_ref()
+                .map(TransportOptions::from),
             router_rtp_capabilities: self.vc.router().rtp_capabilities().clone(),
+            settings: Box::new(self.vc.settings()),
         };
         let address = ctx.address();
         address.do_send(server_init_message);
 
-        for peer_id in self.vc.get_all_peers() {
-            address.do_send(S2C::Notification(Notification::PeerJoin { peer_id }));
+        for (peer_id, metadata, presence) in self.vc.get_all_peers() {
+            if peer_id == self.id {
+                continue;
+            }
+            let timestamp = clock::now_millis();
+            let presence = presence.notifications(&peer_id);
+            address.do_send(S2C::Notification(
+                Notification::PeerJoin { peer_id, metadata }.stamp(timestamp),
+            ));
+            for notification in presence {
+                address.do_send(S2C::Notification(notification.stamp(timestamp)));
+            }
         }
 
-        self.vc.add_peer(self.id.clone());
+        self.vc
+            .set_debug_recipient(&self.id, self.session_id, address.clone().recipient());
+
+        self.attached_handlers.push(self.vc.on_kick({
+            let own_peer_id = self.id.clone();
+            let own_session_id = self.session_id;
+            let address = address.clone();
+
+            move |peer_id, session_id, reason| {
+                if peer_id == &own_peer_id && session_id == &own_session_id {
+                    address.do_send(InternalMessage::Close(
+                        ws::CloseCode::Policy,
+                        reason.clone(),
+                    ));
+                }
+            }
+        }));
+        if let Some(reason) = self.vc.take_kick(self.session_id) {
+            address.do_send(InternalMessage::Close(ws::CloseCode::Policy, reason));
+        }
+
+        self.attached_handlers.push(self.vc.on_going_away({
+            let address = address.clone();
+
+            move |notice| {
+                address.do_send(S2C::GoingAway(notice.clone()));
+            }
+        }));
+
+        self.attached_handlers.push(self.vc.on_disconnect({
+            let address = address.clone();
+
+            move |reason| {
+                address.do_send(InternalMessage::Close(ws::CloseCode::Away, reason.clone()));
+            }
+        }));
+
+        self.attached_handlers.push(self.vc.on_room_closed({
+            let address = address.clone();
+
+            move |reason| {
+                address.do_send(S2C::RoomClosed {
+                    reason: reason.clone(),
+                });
+                address.do_send(InternalMessage::Close(
+                    ws::CloseCode::Normal,
+                    reason.clone(),
+                ));
+            }
+        }));
 
         self.attached_handlers.push(self.vc.on_notification({
             let own_peer_id = self.id.clone();
             let address = address.clone();
 
-            move |notification| {
-                if let Some(peer_id) = notification.associated_peer_id() {
+            move |stamped| {
+                if let Some(peer_id) = stamped.notification.associated_peer_id() {
                     if peer_id == &own_peer_id {
                         return;
                     }
                 }
-                address.do_send(S2C::Notification(notification.clone()));
+                address.do_send(S2C::Notification(stamped.clone()));
             }
         }));
 
-        self.attached_handlers.push(self.vc.on_echo({
-            let own_peer_id = self.id.clone();
+        self.attached_handlers.push(self.vc.on_chat({
             let address = address.clone();
 
-            move |peer_id, text| {
-                if &own_peer_id == peer_id {
-                    return;
-                }
-                address.do_send(S2C::Echo {
-                    peer_id: peer_id.clone(),
-                    text: text.clone(),
-                });
+            move |event| {
+                address.do_send(S2C::Chat(event.clone()));
+            }
+        }));
+
+        self.attached_handlers.push(self.vc.on_settings({
+            let address = address.clone();
+
+            move |settings| {
+                address.do_send(S2C::Settings(settings.clone()));
+            }
+        }));
+
+        self.attached_handlers.push(self.vc.on_playback({
+            let address = address.clone();
+
+            move |state| {
+                address.do_send(S2C::Playback(state.clone()));
             }
         }));
 
         self.attached_handlers.push(self.vc.on_producer_add({
             let own_peer_id = self.id.clone();
             let address = address.clone();
+            let auto_subscribe = self.options.auto_subscribe.clone();
 
             move |peer_id, producer| {
                 if &own_peer_id == peer_id {
                     return;
                 }
-                address.do_send(S2C::ProducerAdd {
-                    peer_id: peer_id.clone(),
-                    producer_id: producer.id(),
-                });
+                address.do_send(S2C::producer_add(peer_id.clone(), producer));
+                if auto_subscribe.matches(&ProducerInfo::of(producer)) {
+                    address.do_send(InternalMessage::AutoConsume(producer.id()));
+                }
+            }
+        }));
+
+        self.attached_handlers.push(self.vc.on_speakers_change({
+            let address = address.clone();
+
+            move |speakers| {
+                address.do_send(InternalMessage::SpeakersChange(speakers.clone()));
             }
         }));
 
+        if let Some(interval) = self.vc.config().quality.interval {
+            ctx.run_interval(interval, |act, ctx| {
+                let _entered = act.span.clone().entered();
+                act.sample_quality(ctx);
+            });
+        }
+
+        if let Some(interval) = self.vc.config().score_interval {
+            ctx.run_interval(interval, |act, ctx| act.send_scores(ctx));
+        }
+
+        if self.vc.has_budget() {
+            self.attached_handlers.push(self.vc.on_layer_cap_change({
+                let address = address.clone();
+
+                move |layer_cap| {
+                    address.do_send(InternalMessage::LayerCap(*layer_cap));
+                }
+            }));
+
+            ctx.run_interval(self.vc.config().bandwidth.sample_interval, |act, _ctx| {
+                let _entered = act.span.clone().entered();
+                act.report_outgoing_bitrate();
+            });
+        }
+
         self.attached_handlers.push(self.vc.on_producer_remove({
             let own_peer_id = self.id.clone();
             let address = address.clone();
@@ -163,24 +960,39 @@ impl Actor for PeerConnection {
             }
         }));
 
-        for (peer_id, producer_id) in self.vc.get_all_producers() {
-            address.do_send(S2C::ProducerAdd {
-                peer_id,
-                producer_id,
-            });
+        for (peer_id, producer) in self.vc.get_all_producers() {
+            if peer_id != self.id {
+                address.do_send(S2C::producer_add(peer_id, &producer));
+            }
         }
+
+        for peer_id in self.vc.hands() {
+            address.do_send(S2C::Notification(
+                Notification::HandRaise { peer_id }.stamp(clock::now_millis()),
+            ));
+        }
+
+        address.do_send(S2C::ChatHistory {
+            messages: self.vc.chat_history(),
+        });
+
+        if let Some(peer_id) = self.vc.host() {
+            address.do_send(S2C::Notification(
+                Notification::HostChange { peer_id }.stamp(clock::now_millis()),
+            ));
+        }
+        address.do_send(S2C::Playback(self.vc.playback_state()));
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        dbg!(
-            "[peer_id {:?}] WebSocket connection closed",
-            self.id.clone()
-        );
+        let _entered = self.span.enter();
+        tracing::info!("WebSocket connection closed");
     }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for PeerConnection {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let _entered = self.span.clone().entered();
         match msg {
             Ok(ws::Message::Ping(msg)) => {
                 ctx.pong(&msg);
@@ -188,19 +1000,42 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for PeerConnection {
             Ok(ws::Message::Pong(_)) => {}
             Ok(ws::Message::Text(text)) => match serde_json::from_str::<C2S>(&text) {
                 Ok(message) => {
-                    ctx.address().do_send(message);
+                    if !self.check_rate_limit(message.action(), ctx) {
+                        return;
+                    }
+
+                    match message {
+                        C2S::TimeSync { client_send_time } => {
+                            // Answered right away, going through the mailbox would skew the sample
+                            self.time_sync(client_send_time, clock::now_millis(), ctx);
+                        }
+                        message => ctx.address().do_send(message),
+                    }
                 }
                 Err(error) => {
-                    eprintln!("Failed to parse client message: {error}\n{text}");
+                    if self.check_rate_limit(Self::INVALID_ACTION, ctx) {
+                        let text = text.chars().take(Self::MAX_LOGGED_TEXT).collect::<String>();
+                        tracing::warn!(%text, "Failed to parse client message: {error}");
+                    }
                 }
             },
             Ok(ws::Message::Binary(bin)) => {
-                eprintln!("Unexpected binary message: {bin:?}");
+                if self.check_rate_limit(Self::INVALID_ACTION, ctx) {
+                    tracing::warn!(len = bin.len(), "Unexpected binary message");
+                }
             }
             Ok(ws::Message::Close(reason)) => {
                 ctx.close(reason);
                 ctx.stop();
             }
+            Err(ws::ProtocolError::Overflow) => {
+                tracing::warn!("Client message exceeds the maximum frame size");
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Size,
+                    description: Some("Message too large".to_string()),
+                }));
+                ctx.stop();
+            }
             _ => ctx.stop(),
         }
     }
@@ -210,15 +1045,51 @@ impl Handler<C2S> for PeerConnection {
     type Result = ();
 
     fn handle(&mut self, message: C2S, ctx: &mut Self::Context) -> Self::Result {
+        let _entered = self.span.clone().entered();
         match message {
             C2S::Init { rtp_capabilities } => {
                 self.client_rtp_capabilities.replace(rtp_capabilities);
+                self.start_auto_subscribe(ctx);
+            }
+            C2S::CreateProducerTransport => {
+                if let Err(message) = self.can_publish() {
+                    ctx.address().do_send(S2C::Error { message });
+                    return;
+                }
+                if self.transports.producer.is_some() || self.transports.producer_pending {
+                    ctx.address().do_send(S2C::Error {
+                        message: "Producer transport already created".to_string(),
+                    });
+                    return;
+                }
+
+                self.transports.producer_pending = true;
+                let address = ctx.address();
+                let vc = self.vc.clone();
+                let max_incoming = vc.config().bandwidth.limits(self.options.role).max_incoming;
+                self.spawn(async move {
+                    match create_transport(&vc, max_incoming, None).await {
+                        Ok(transport) => {
+                            address.do_send(InternalMessage::SaveProducerTransport(transport));
+                        }
+                        Err(error) => {
+                            tracing::error!("Failed to create producer transport: {error}");
+                            address.do_send(InternalMessage::Stop);
+                        }
+                    }
+                });
             }
             C2S::ConnectProducerTransport { dtls_parameters } => {
                 let address = ctx.address();
-                let transport = self.transports.producer.clone();
+                let transport = match self.producer_transport() {
+                    Ok(transport) => transport,
+                    Err(message) => {
+                        address.do_send(S2C::Error { message });
+                        return;
+                    }
+                };
 
-                actix::spawn(async move {
+                self.spawn(async move {
                     match transport
                         .connect(WebRtcTransportRemoteParameters { dtls_parameters })
                         .await
@@ -227,7 +1098,7 @@ impl Handler<C2S> for PeerConnection {
                             address.do_send(S2C::ConnectedProducerTransport);
                         }
                         Err(error) => {
-                            eprintln!("Failed to connect producer transport: {error}");
+                            tracing::error!("Failed to connect producer transport: {error}");
                             address.do_send(InternalMessage::Stop);
                         }
                     }
@@ -236,121 +1107,176 @@ impl Handler<C2S> for PeerConnection {
             C2S::Produce {
                 kind,
                 rtp_parameters,
+                source,
+                app_data,
             } => {
                 let peer_id = self.id.clone();
                 let address = ctx.address();
-                let transport = self.transports.producer.clone();
+                let transport = match self.producer_transport() {
+                    Ok(transport) => transport,
+                    Err(message) => {
+                        address.do_send(S2C::Error { message });
+                        return;
+                    }
+                };
+                let info = ProducerInfo {
+                    source: source.unwrap_or_else(|| ProducerSource::default_for(kind)),
+                    app_data,
+                };
+                if let Err(message) = info.validate() {
+                    address.do_send(S2C::Error { message });
+                    return;
+                }
                 let vc = self.vc.clone();
-                actix::spawn(async move {
-                    match transport
-                        .produce(ProducerOptions::new(kind, rtp_parameters))
-                        .await
-                    {
+                let mut options = ProducerOptions::new(kind, rtp_parameters);
+                options.app_data = AppData::new(info);
+                self.spawn(async move {
+                    match transport.produce(options).await {
                         Ok(producer) => {
                             let id = producer.id();
-                            address.do_send(S2C::ProducerCreated { id });
-                            vc.add_producer(peer_id, producer.clone());
+                            tracing::debug!(producer_id = %id, ?kind, "Producer created");
+                            // Dropping a rejected producer closes it
+                            if let Err(message) = vc.add_producer(peer_id, producer.clone()).await {
+                                address.do_send(S2C::Error { message });
+                                return;
+                            }
+                            // Saved first, so that a `C2S::ProducerRemove` for it finds it
                             address.do_send(InternalMessage::SaveProducer(producer));
+                            address.do_send(S2C::ProducerCreated { id });
                         }
                         Err(error) => {
-                            eprintln!("{}", error);
+                            tracing::error!(?kind, "Failed to produce: {error}");
                             address.do_send(InternalMessage::Stop);
                         }
                     }
                 });
             }
-            C2S::ProducerRemove { producer_id } => self.vc.remove_producer(&self.id, &producer_id),
+            C2S::ProducerRemove { producer_id } => {
+                // Other peers' producers are not this peer's to remove
+                let Some(index) = self
+                    .producers
+                    .iter()
+                    .position(|producer| producer.id() == producer_id)
+                else {
+                    return;
+                };
+                // Dropping the producer closes it, along with its consumers
+                drop(self.producers.remove(index));
+                self.vc.remove_producer(&self.id, &producer_id);
+            }
 
             C2S::ConnectConsumerTransport { dtls_parameters } => {
-                let peer_id = self.id.clone();
                 let address = ctx.address();
                 let transport = self.transports.consumer.clone();
 
-                actix::spawn(async move {
+                self.spawn(async move {
                     match transport
                         .connect(WebRtcTransportRemoteParameters { dtls_parameters })
                         .await
                     {
                         Ok(_) => {
                             address.do_send(S2C::ConnectedConsumerTransport);
-                            println!("[peer_id {peer_id:?}] Consumer transport connected");
+                            address.do_send(InternalMessage::ConsumerTransportConnected);
+                            tracing::debug!("Consumer transport connected");
                         }
                         Err(error) => {
-                            eprintln!(
-                                "[peer_id {peer_id:?}] Failed to connect consumer transport: {error}"
-                            );
+                            tracing::error!("Failed to connect consumer transport: {error}");
                             address.do_send(InternalMessage::Stop);
                         }
                     }
                 });
             }
-            C2S::Consume { producer_id } => {
-                let peer_id = self.id.clone();
+            C2S::Consume { producer_id } => self.consume(producer_id, false, ctx),
+            C2S::ConsumerResume { id } => {
+                if let Some(entry) = self.consumers.get_mut(&id) {
+                    entry.resumed = true;
+                    self.update_consumers(ctx);
+                }
+            }
+            C2S::Subscribe { rules } => {
+                self.subscription = rules;
+                self.update_consumers(ctx);
+            }
+            C2S::ChatSend { text, nonce } => match self.vc.chat_send(&self.id, text) {
+                Ok(id) => ctx.address().do_send(S2C::ChatAck { nonce, id }),
+                Err(message) => ctx.address().do_send(S2C::Error { message }),
+            },
+            C2S::ChatEdit { id, text } => {
+                if let Err(message) = self.vc.chat_edit(&self.id, id, text) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::ChatDelete { id } => {
+                if let Err(message) = self.vc.chat_delete(&self.id, id) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::Notification { kind } => self.vc.notify(&self.id, &kind),
+            C2S::TimeSync { client_send_time } => {
+                self.time_sync(client_send_time, clock::now_millis(), ctx);
+            }
+            C2S::UpdateSettings { settings } => {
+                if let Err(message) = self.vc.update_settings(&self.id, settings) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::UpdateMetadata { metadata } => {
+                if let Err(message) = self.vc.update_metadata(&self.id, metadata) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::Signal { signal, payload } => {
+                if let Err(message) = self.vc.signal(&self.id, signal, payload) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::RaiseHand => {
+                if let Err(message) = self.vc.raise_hand(&self.id) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::LowerHand { peer_id } => {
+                if let Err(message) = self.vc.lower_hand(&self.id, peer_id) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::ClearHands => {
+                if let Err(message) = self.vc.clear_hands(&self.id) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::GetHands => {
+                if self.vc.is_host(&self.id) {
+                    ctx.address().do_send(S2C::HandQueue {
+                        peers: self.vc.hands(),
+                    });
+                } else {
+                    ctx.address().do_send(S2C::Error {
+                        message: "Only the host can inspect raised hands".to_string(),
+                    });
+                }
+            }
+            C2S::Playback { command } => {
+                if let Err(message) = self.vc.playback(&self.id, command) {
+                    ctx.address().do_send(S2C::Error { message });
+                }
+            }
+            C2S::GetStats { target } => {
                 let address = ctx.address();
-                let transport = self.transports.consumer.clone();
-                let rtp_capabilities = match self.client_rtp_capabilities.clone() {
-                    Some(rtp_capabilities) => rtp_capabilities,
-                    None => {
-                        eprintln!(
-                            "[peer_id {peer_id:?}] Client should send RTP capabilities before \
-                            consuming"
-                        );
-                        return;
-                    }
+                let Some(source) = self.stats_source(&target) else {
+                    address.do_send(S2C::Error {
+                        message: format!("Unknown stats target {target:?}"),
+                    });
+                    return;
                 };
-                actix::spawn(async move {
-                    let mut options = ConsumerOptions::new(producer_id, rtp_capabilities);
-                    options.paused = true;
-
-                    match transport.consume(options).await {
-                        Ok(consumer) => {
-                            let id = consumer.id();
-                            let kind = consumer.kind();
-                            let rtp_parameters = consumer.rtp_parameters().clone();
-                            address.do_send(S2C::ConsumerCreated {
-                                id,
-                                producer_id,
-                                kind,
-                                rtp_parameters,
-                            });
-                            address.do_send(InternalMessage::SaveConsumer(consumer));
-                            println!("[peer_id {peer_id:?}] {kind:?} consumer created: {id}");
-                        }
-                        Err(error) => {
-                            eprintln!("[peer_id {peer_id:?}] Failed to create consumer: {error}");
-                            address.do_send(InternalMessage::Stop);
-                        }
+
+                self.spawn(async move {
+                    match source.get_stats().await {
+                        Ok(stats) => address.do_send(S2C::Stats { target, stats }),
+                        Err(message) => address.do_send(S2C::Error { message }),
                     }
                 });
             }
-            C2S::ConsumerResume { id } => {
-                if let Some(consumer) = self.consumers.get(&id).cloned() {
-                    let peer_id = self.id.clone();
-                    actix::spawn(async move {
-                        match consumer.resume().await {
-                            Ok(_) => {
-                                println!(
-                                    "[peer_id {:?}] Successfully resumed {:?} consumer {}",
-                                    peer_id,
-                                    consumer.kind(),
-                                    consumer.id(),
-                                );
-                            }
-                            Err(error) => {
-                                println!(
-                                    "[peer_id {:?}] Failed to resume {:?} consumer {}: {}",
-                                    peer_id,
-                                    consumer.kind(),
-                                    consumer.id(),
-                                    error,
-                                );
-                            }
-                        }
-                    });
-                }
-            }
-            C2S::Echo { text } => self.vc.echo(&self.id, &text),
-            C2S::Notification { kind } => self.vc.notify(&self.id, &kind),
         }
     }
 }
@@ -364,19 +1290,86 @@ impl Handler<S2C> for PeerConnection {
     }
 }
 
+impl Handler<DebugState> for PeerConnection {
+    type Result = MessageResult<DebugState>;
+
+    fn handle(&mut self, _message: DebugState, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.debug_state())
+    }
+}
+
 impl Handler<InternalMessage> for PeerConnection {
     type Result = ();
 
     fn handle(&mut self, message: InternalMessage, ctx: &mut Self::Context) {
+        let _entered = self.span.clone().entered();
         match message {
             InternalMessage::Stop => {
                 ctx.stop();
             }
+            InternalMessage::Close(code, reason) => {
+                ctx.close(Some(ws::CloseReason {
+                    code,
+                    description: Some(reason),
+                }));
+                ctx.stop();
+            }
+            InternalMessage::SaveProducerTransport(transport) => {
+                self.transports.producer_pending = false;
+                self.vc.add_transport(&self.id, &transport);
+                ctx.address().do_send(S2C::ProducerTransportCreated {
+                    options: TransportOptions::from(&transport),
+                });
+                self.transports.producer = Some(transport);
+            }
             InternalMessage::SaveProducer(producer) => {
                 self.producers.push(producer);
             }
-            InternalMessage::SaveConsumer(consumer) => {
-                self.consumers.insert(consumer.id(), consumer);
+            InternalMessage::SaveConsumer { consumer, resumed } => {
+                self.consumers_pending = self.consumers_pending.saturating_sub(1);
+                // The producer may have gone away while the consumer was being created
+                let Some((peer_id, info)) = self.vc.producer_owner(&consumer.producer_id()) else {
+                    return;
+                };
+                self.vc.add_consumer(&self.id, &consumer);
+                let producer_close_handler = consumer.on_producer_close({
+                    let id = consumer.id();
+                    let address = ctx.address();
+
+                    move || address.do_send(InternalMessage::ConsumerClosed(id))
+                });
+                self.consumers.insert(
+                    consumer.id(),
+                    ConsumerEntry {
+                        consumer,
+                        peer_id,
+                        source: info.source,
+                        resumed,
+                        _producer_close_handler: producer_close_handler,
+                    },
+                );
+                if resumed {
+                    self.update_consumers(ctx);
+                }
+            }
+            InternalMessage::ConsumerClosed(id) => {
+                self.consumers.remove(&id);
+                // A freed last-N slot may go to someone else
+                self.update_consumers(ctx);
+            }
+            InternalMessage::SpeakersChange(speakers) => {
+                self.speakers = speakers;
+                self.update_consumers(ctx);
+            }
+            InternalMessage::ConsumerTransportConnected => {
+                self.consumer_transport_connected = true;
+            }
+            InternalMessage::AutoConsume(producer_id) => {
+                self.auto_consume(producer_id, ctx);
+            }
+            InternalMessage::LayerCap(layer_cap) => {
+                self.layer_cap = layer_cap;
+                self.apply_layer_cap();
             }
         }
     }
@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+
+/// Room wide media playback, driven by the host and mirrored by every peer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackState {
+    pub media_url: Option<String>,
+    /// Position in seconds at `updated_at`.
+    pub position: f64,
+    pub rate: f64,
+    pub paused: bool,
+    /// Server time (ms since the Unix epoch) at which `position` was sampled.
+    pub updated_at: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum PlaybackCommand {
+    #[serde(rename_all = "camelCase")]
+    Load {
+        media_url: String,
+    },
+
+    Play {
+        position: Option<f64>,
+    },
+
+    Pause {
+        position: Option<f64>,
+    },
+
+    Seek {
+        position: f64,
+    },
+
+    SetRate {
+        rate: f64,
+    },
+}
+
+impl PlaybackState {
+    pub fn new(now: u64) -> Self {
+        Self {
+            media_url: None,
+            position: 0.0,
+            rate: 1.0,
+            paused: true,
+            updated_at: now,
+        }
+    }
+
+    /// Position extrapolated to server time `now`.
+    pub fn position_at(&self, now: u64) -> f64 {
+        if self.paused {
+            return self.position;
+        }
+
+        let elapsed = now.saturating_sub(self.updated_at) as f64 / 1000.0;
+        self.position + elapsed * self.rate
+    }
+
+    pub fn apply(&mut self, command: PlaybackCommand, now: u64) -> Result<(), String> {
+        let mut next = self.clone();
+        next.position = match command {
+            PlaybackCommand::Load { media_url } => {
+                next.media_url = Some(media_url);
+                next.paused = true;
+                0.0
+            }
+            PlaybackCommand::Play { position } => {
+                next.paused = false;
+                position.unwrap_or_else(|| self.position_at(now))
+            }
+            PlaybackCommand::Pause { position } => {
+                next.paused = true;
+                position.unwrap_or_else(|| self.position_at(now))
+            }
+            PlaybackCommand::Seek { position } => position,
+            PlaybackCommand::SetRate { rate } => {
+                if !rate.is_finite() || rate <= 0.0 {
+                    return Err(format!("Invalid playback rate {rate}"));
+                }
+                next.rate = rate;
+                self.position_at(now)
+            }
+        };
+        next.updated_at = now;
+
+        if !next.position.is_finite() || next.position < 0.0 {
+            return Err(format!("Invalid playback position {}", next.position));
+        }
+        if next.media_url.is_none() {
+            return Err("No media loaded".to_string());
+        }
+
+        *self = next;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded() -> PlaybackState {
+        let mut state = PlaybackState::new(0);
+        state
+            .apply(
+                PlaybackCommand::Load {
+                    media_url: "https://example.com/video.mp4".to_string(),
+                },
+                0,
+            )
+            .unwrap();
+        state
+    }
+
+    #[test]
+    fn commands_need_media() {
+        let mut state = PlaybackState::new(0);
+        assert!(state
+            .apply(PlaybackCommand::Play { position: None }, 1000)
+            .is_err());
+        assert!(state.paused);
+        assert_eq!(state.updated_at, 0);
+    }
+
+    #[test]
+    fn play_extrapolates_position() {
+        let mut state = loaded();
+        state
+            .apply(PlaybackCommand::Play { position: None }, 1000)
+            .unwrap();
+        assert_eq!(state.position_at(3000), 2.0);
+
+        state
+            .apply(PlaybackCommand::Pause { position: None }, 4000)
+            .unwrap();
+        assert_eq!(state.position, 3.0);
+        assert_eq!(state.position_at(10_000), 3.0);
+    }
+
+    #[test]
+    fn rate_scales_elapsed_time() {
+        let mut state = loaded();
+        state
+            .apply(
+                PlaybackCommand::Play {
+                    position: Some(10.0),
+                },
+                0,
+            )
+            .unwrap();
+        state
+            .apply(PlaybackCommand::SetRate { rate: 2.0 }, 1000)
+            .unwrap();
+        assert_eq!(state.position, 11.0);
+        assert_eq!(state.position_at(2000), 13.0);
+    }
+
+    #[test]
+    fn rejects_invalid_rate() {
+        let mut state = loaded();
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(state
+                .apply(PlaybackCommand::SetRate { rate }, 1000)
+                .is_err());
+        }
+        assert_eq!(state.rate, 1.0);
+    }
+
+    #[test]
+    fn rejects_invalid_seek() {
+        let mut state = loaded();
+        state
+            .apply(PlaybackCommand::Seek { position: 5.0 }, 1000)
+            .unwrap();
+        for position in [-1.0, f64::NAN] {
+            assert!(state
+                .apply(PlaybackCommand::Seek { position }, 2000)
+                .is_err());
+        }
+        assert_eq!(state.position, 5.0);
+        assert_eq!(state.updated_at, 1000);
+    }
+
+    #[test]
+    fn load_resets_position() {
+        let mut state = loaded();
+        state
+            .apply(
+                PlaybackCommand::Play {
+                    position: Some(30.0),
+                },
+                0,
+            )
+            .unwrap();
+        state
+            .apply(
+                PlaybackCommand::Load {
+                    media_url: "https://example.com/other.mp4".to_string(),
+                },
+                1000,
+            )
+            .unwrap();
+        assert!(state.paused);
+        assert_eq!(state.position, 0.0);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use mediasoup::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What a producer's track carries, so other peers can tell a webcam from a screen share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProducerSource {
+    Microphone,
+    Camera,
+    Screen,
+    ScreenAudio,
+    /// A shared media file.
+    Media,
+    Other,
+}
+
+impl ProducerSource {
+    pub fn default_for(kind: MediaKind) -> Self {
+        match kind {
+            MediaKind::Audio => Self::Microphone,
+            MediaKind::Video => Self::Camera,
+        }
+    }
+}
+
+impl FromStr for ProducerSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| format!("Unknown producer source {s:?}"))
+    }
+}
+
+/// Stored as the app data of every [`Producer`] created for a peer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProducerInfo {
+    pub source: ProducerSource,
+    /// Arbitrary client supplied JSON.
+    #[serde(skip_serializing_if = "serde_json::Value::is_null")]
+    pub app_data: serde_json::Value,
+}
+
+impl ProducerInfo {
+    /// Same limit as [`crate::peer::PeerMetadata`], the app data is sent to every peer.
+    const MAX_APP_DATA_SIZE: usize = 4096;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.app_data.to_string().len() > Self::MAX_APP_DATA_SIZE {
+            return Err(format!(
+                "appData is larger than {} bytes",
+                Self::MAX_APP_DATA_SIZE
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn of(producer: &Producer) -> Self {
+        producer
+            .app_data()
+            .downcast_ref::<Self>()
+            .cloned()
+            .unwrap_or_else(|| Self {
+                source: ProducerSource::default_for(producer.kind()),
+                app_data: serde_json::Value::Null,
+            })
+    }
+}
+
+/// Maximum number of producers per source in a room. Parsed from a comma separated list of
+/// `<source>=<count>`, e.g. `"screen=1,media=1"`.
+#[derive(Debug, Clone, Default)]
+pub struct SourceLimits(HashMap<ProducerSource, usize>);
+
+impl SourceLimits {
+    pub fn get(&self, source: ProducerSource) -> Option<usize> {
+        self.0.get(&source).copied()
+    }
+}
+
+impl FromStr for SourceLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (source, count) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected <source>=<count>, got {entry:?}"))?;
+                let count = count
+                    .trim()
+                    .parse()
+                    .map_err(|error| format!("Invalid count in {entry:?}: {error}"))?;
+                Ok((source.trim().parse()?, count))
+            })
+            .collect::<Result<_, String>>()
+            .map(Self)
+    }
+}
+
+/// Maximum number of producers per media kind a single peer may have. Parsed from a comma
+/// separated list of `<kind>=<count>`, e.g. `"audio=1,video=2"`.
+#[derive(Debug, Clone, Default)]
+pub struct KindLimits {
+    audio: Option<usize>,
+    video: Option<usize>,
+}
+
+impl KindLimits {
+    pub fn get(&self, kind: MediaKind) -> Option<usize> {
+        match kind {
+            MediaKind::Audio => self.audio,
+            MediaKind::Video => self.video,
+        }
+    }
+}
+
+impl FromStr for KindLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Self::default();
+        for entry in s.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (kind, count) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <kind>=<count>, got {entry:?}"))?;
+            let count = count
+                .trim()
+                .parse()
+                .map_err(|error| format!("Invalid count in {entry:?}: {error}"))?;
+            match kind.trim() {
+                "audio" => limits.audio = Some(count),
+                "video" => limits.video = Some(count),
+                kind => return Err(format!("Unknown media kind {kind:?}")),
+            }
+        }
+
+        Ok(limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_source() {
+        assert_eq!("screen".parse(), Ok(ProducerSource::Screen));
+        assert_eq!("screenAudio".parse(), Ok(ProducerSource::ScreenAudio));
+        assert!("Screen".parse::<ProducerSource>().is_err());
+        assert!("webcam".parse::<ProducerSource>().is_err());
+    }
+
+    #[test]
+    fn parses_source_limits() {
+        let limits: SourceLimits = " screen=1, media = 2 ,".parse().unwrap();
+        assert_eq!(limits.get(ProducerSource::Screen), Some(1));
+        assert_eq!(limits.get(ProducerSource::Media), Some(2));
+        assert_eq!(limits.get(ProducerSource::Camera), None);
+
+        assert!("".parse::<SourceLimits>().unwrap().0.is_empty());
+        for s in ["screen", "screen=x", "webcam=1", "screen=-1"] {
+            assert!(s.parse::<SourceLimits>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parses_kind_limits() {
+        let limits: KindLimits = "audio=1, video = 2".parse().unwrap();
+        assert_eq!(limits.get(MediaKind::Audio), Some(1));
+        assert_eq!(limits.get(MediaKind::Video), Some(2));
+
+        let limits: KindLimits = "video=0".parse().unwrap();
+        assert_eq!(limits.get(MediaKind::Audio), None);
+        assert_eq!(limits.get(MediaKind::Video), Some(0));
+
+        for s in ["audio", "audio=x", "data=1", "Audio=1"] {
+            assert!(s.parse::<KindLimits>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn caps_app_data_size() {
+        let info = |app_data| ProducerInfo {
+            source: ProducerSource::Camera,
+            app_data,
+        };
+        assert!(info(serde_json::Value::Null).validate().is_ok());
+        assert!(info(serde_json::json!({ "label": "x".repeat(4000) }))
+            .validate()
+            .is_ok());
+        assert!(info(serde_json::json!({ "label": "x".repeat(4100) }))
+            .validate()
+            .is_err());
+    }
+}
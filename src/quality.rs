@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone)]
+pub struct QualityConfig {
+    /// How often scores are sampled, `None` disables quality tracking.
+    pub interval: Option<Duration>,
+    /// Number of samples the rolling average spans.
+    pub window: usize,
+}
+
+/// Coarse connection quality derived from mediasoup's 0 to 10 RTP stream scores.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NetworkQuality {
+    Poor,
+    Fair,
+    Good,
+}
+
+impl NetworkQuality {
+    /// Extra score an improvement needs on top of the threshold, keeps a quality from flapping
+    /// around a boundary.
+    const RECOVERY_MARGIN: f64 = 1.0;
+
+    fn from_score(score: f64) -> Self {
+        if score >= 7.0 {
+            NetworkQuality::Good
+        } else if score >= 4.0 {
+            NetworkQuality::Fair
+        } else {
+            NetworkQuality::Poor
+        }
+    }
+}
+
+/// Rolling average of score samples in one direction of a peer's connection.
+pub struct QualityTracker {
+    window: usize,
+    samples: VecDeque<u8>,
+    quality: NetworkQuality,
+    /// Sampling intervals in a row without a score, see [`Self::idle`].
+    idle: usize,
+}
+
+impl QualityTracker {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            samples: VecDeque::new(),
+            quality: NetworkQuality::Good,
+            idle: 0,
+        }
+    }
+
+    pub fn quality(&self) -> NetworkQuality {
+        self.quality
+    }
+
+    /// Adds a 0 to 10 score, returning the new quality when it changed.
+    pub fn sample(&mut self, score: u8) -> Option<NetworkQuality> {
+        self.idle = 0;
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(score);
+
+        let average = self
+            .samples
+            .iter()
+            .map(|&score| f64::from(score))
+            .sum::<f64>()
+            / self.samples.len() as f64;
+        let mut quality = NetworkQuality::from_score(average);
+        if quality > self.quality {
+            quality = NetworkQuality::from_score(average - NetworkQuality::RECOVERY_MARGIN)
+                .max(self.quality);
+        }
+        if quality == self.quality {
+            return None;
+        }
+
+        self.quality = quality;
+
+        Some(quality)
+    }
+
+    /// Notes a sampling interval without any score, as when everything was paused for a poor
+    /// quality. After a window of those a poor quality is raised to fair, so that media flows
+    /// again and shows whether the connection recovered. Returns the new quality when it changed.
+    pub fn idle(&mut self) -> Option<NetworkQuality> {
+        if self.quality != NetworkQuality::Poor {
+            return None;
+        }
+        self.idle += 1;
+        if self.idle < self.window {
+            return None;
+        }
+
+        self.idle = 0;
+        self.samples.clear();
+        self.quality = NetworkQuality::Fair;
+
+        Some(NetworkQuality::Fair)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degrades_on_the_average() {
+        let mut tracker = QualityTracker::new(3);
+        assert_eq!(tracker.sample(10), None);
+        // Averages to 7, still Good
+        assert_eq!(tracker.sample(4), None);
+        assert_eq!(tracker.sample(2), Some(NetworkQuality::Fair));
+        assert_eq!(tracker.sample(2), Some(NetworkQuality::Poor));
+    }
+
+    #[test]
+    fn recovery_needs_the_margin() {
+        let mut tracker = QualityTracker::new(1);
+        assert_eq!(tracker.sample(2), Some(NetworkQuality::Poor));
+        // At the Fair threshold, but not above it by the margin
+        assert_eq!(tracker.sample(4), None);
+        assert_eq!(tracker.sample(5), Some(NetworkQuality::Fair));
+        assert_eq!(tracker.sample(7), None);
+        assert_eq!(tracker.sample(8), Some(NetworkQuality::Good));
+        // Degrading needs no margin
+        assert_eq!(tracker.sample(6), Some(NetworkQuality::Fair));
+    }
+
+    #[test]
+    fn probes_after_a_window_without_scores() {
+        let mut tracker = QualityTracker::new(3);
+        // Only a poor quality probes
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.sample(0), Some(NetworkQuality::Poor));
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), Some(NetworkQuality::Fair));
+        assert_eq!(tracker.idle(), None);
+
+        // The probe is judged on its own scores, not the ones from before
+        assert_eq!(tracker.sample(0), Some(NetworkQuality::Poor));
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.sample(0), None);
+        // A score restarts the count
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), None);
+        assert_eq!(tracker.idle(), Some(NetworkQuality::Fair));
+        assert_eq!(tracker.sample(6), None);
+    }
+
+    #[test]
+    fn recovers_partially_within_the_margin() {
+        let mut tracker = QualityTracker::new(1);
+        tracker.sample(0);
+        // Good on its own, only Fair once the margin is taken off
+        assert_eq!(tracker.sample(7), Some(NetworkQuality::Fair));
+    }
+}
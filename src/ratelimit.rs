@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Allows `burst` events, refilled evenly over `period`. Parsed from `"<burst>/<seconds>"`, so
+/// `"5/10"` is five events per ten seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(burst: u32, period: Duration) -> Self {
+        Self { burst, period }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (burst, seconds) = s
+            .split_once('/')
+            .ok_or_else(|| format!("Expected <burst>/<seconds>, got {s:?}"))?;
+        let burst = burst
+            .trim()
+            .parse()
+            .map_err(|error| format!("Invalid burst in {s:?}: {error}"))?;
+        let seconds: f64 = seconds
+            .trim()
+            .parse()
+            .map_err(|error| format!("Invalid period in {s:?}: {error}"))?;
+        if !seconds.is_finite() || seconds <= 0.0 {
+            return Err(format!("Invalid period in {s:?}"));
+        }
+
+        Ok(Self::new(burst, Duration::from_secs_f64(seconds)))
+    }
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: f64::from(limit.burst),
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available.
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        let burst = f64::from(self.limit.burst);
+        self.tokens = (self.tokens + elapsed * burst / self.limit.period.as_secs_f64()).min(burst);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Per connection limits on client messages, see [`MessageLimiter`].
+#[derive(Debug, Clone)]
+pub struct MessageLimitConfig {
+    /// Largest WebSocket frame accepted, in bytes. Fragmented messages are not accepted, so this
+    /// caps the size of every client message.
+    pub max_frame_size: usize,
+    pub rates: MessageRates,
+    pub escalation: Escalation,
+}
+
+/// Rate limits by client message `action`, with `*` covering every action not listed. Frames that
+/// are not a valid message count as `Invalid`. Parsed from a comma separated list of
+/// `<action>=<burst>/<seconds>`, e.g. `"*=50/10,Notification=5/10,ChatSend=10/10"`.
+#[derive(Debug, Clone)]
+pub struct MessageRates {
+    default: Option<RateLimit>,
+    actions: HashMap<String, RateLimit>,
+}
+
+impl MessageRates {
+    fn get(&self, action: &str) -> Option<RateLimit> {
+        self.actions.get(action).copied().or(self.default)
+    }
+}
+
+impl FromStr for MessageRates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rates = Self {
+            default: None,
+            actions: HashMap::new(),
+        };
+        for entry in s.split(',').filter(|entry| !entry.trim().is_empty()) {
+            let (action, limit) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Expected <action>=<rate limit>, got {entry:?}"))?;
+            match action.trim() {
+                "*" => rates.default = Some(limit.parse()?),
+                action => {
+                    rates.actions.insert(action.to_string(), limit.parse()?);
+                }
+            }
+        }
+
+        Ok(rates)
+    }
+}
+
+/// How a connection exceeding its rate limits is dealt with. The first `drop_after` violations
+/// are only warned about, later messages over the limit are dropped and the connection is closed
+/// on reaching `disconnect_after`. Violations are forgotten after `forgive_after` without any.
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    pub drop_after: u32,
+    pub disconnect_after: u32,
+    pub forgive_after: Duration,
+}
+
+/// What to do with a client message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Over the limit, but still handled.
+    Warn,
+    Drop,
+    Disconnect,
+}
+
+/// Token buckets of one connection, one per message action.
+pub struct MessageLimiter {
+    rates: MessageRates,
+    escalation: Escalation,
+    buckets: HashMap<&'static str, TokenBucket>,
+    violations: u32,
+    violated_at: Option<Instant>,
+}
+
+impl MessageLimiter {
+    pub fn new(config: &MessageLimitConfig) -> Self {
+        Self {
+            rates: config.rates.clone(),
+            escalation: config.escalation.clone(),
+            buckets: HashMap::new(),
+            violations: 0,
+            violated_at: None,
+        }
+    }
+
+    pub fn check(&mut self, action: &'static str) -> Verdict {
+        let Some(limit) = self.rates.get(action) else {
+            return Verdict::Allow;
+        };
+        let bucket = self
+            .buckets
+            .entry(action)
+            .or_insert_with(|| TokenBucket::new(limit));
+        if bucket.try_take() {
+            return Verdict::Allow;
+        }
+
+        let now = Instant::now();
+        if self
+            .violated_at
+            .is_some_and(|at| now.duration_since(at) >= self.escalation.forgive_after)
+        {
+            self.violations = 0;
+        }
+        self.violated_at = Some(now);
+        self.violations += 1;
+
+        if self.violations >= self.escalation.disconnect_after {
+            Verdict::Disconnect
+        } else if self.violations > self.escalation.drop_after {
+            Verdict::Drop
+        } else {
+            Verdict::Warn
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rate_limit() {
+        let limit: RateLimit = "5/10".parse().unwrap();
+        assert_eq!(limit.burst, 5);
+        assert_eq!(limit.period, Duration::from_secs(10));
+
+        let limit: RateLimit = " 3 / 0.5 ".parse().unwrap();
+        assert_eq!(limit.burst, 3);
+        assert_eq!(limit.period, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn rejects_invalid_rate_limit() {
+        for s in ["5", "x/10", "5/x", "5/0", "5/-1", "5/inf", "-1/10"] {
+            assert!(s.parse::<RateLimit>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst() {
+        let mut bucket = TokenBucket::new(RateLimit::new(3, Duration::from_secs(3600)));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    fn limiter(rates: &str, forgive_after: Duration) -> MessageLimiter {
+        MessageLimiter::new(&MessageLimitConfig {
+            max_frame_size: 64 * 1024,
+            rates: rates.parse().unwrap(),
+            escalation: Escalation {
+                drop_after: 2,
+                disconnect_after: 4,
+                forgive_after,
+            },
+        })
+    }
+
+    #[test]
+    fn parses_message_rates() {
+        let rates: MessageRates = "*=50/10, ChatSend=10/10".parse().unwrap();
+        assert_eq!(rates.get("ChatSend").unwrap().burst, 10);
+        assert_eq!(rates.get("Produce").unwrap().burst, 50);
+
+        let rates: MessageRates = "ChatSend=10/10".parse().unwrap();
+        assert!(rates.get("Produce").is_none());
+
+        for s in ["ChatSend", "ChatSend=10", "*=x/10"] {
+            assert!(s.parse::<MessageRates>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn escalates_violations() {
+        let mut limiter = limiter("ChatSend=1/3600", Duration::from_secs(3600));
+        assert_eq!(limiter.check("Produce"), Verdict::Allow);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Allow);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Warn);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Warn);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Drop);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Disconnect);
+    }
+
+    #[test]
+    fn buckets_are_per_action() {
+        let mut limiter = limiter("*=1/3600", Duration::from_secs(3600));
+        assert_eq!(limiter.check("ChatSend"), Verdict::Allow);
+        assert_eq!(limiter.check("Produce"), Verdict::Allow);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Warn);
+    }
+
+    #[test]
+    fn forgives_old_violations() {
+        let mut limiter = limiter("ChatSend=1/3600", Duration::ZERO);
+        assert_eq!(limiter.check("ChatSend"), Verdict::Allow);
+        for _ in 0..10 {
+            assert_eq!(limiter.check("ChatSend"), Verdict::Warn);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use mediasoup::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Who may publish media in a room. Spectators never can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PublishPolicy {
+    #[default]
+    Participants,
+    HostOnly,
+}
+
+/// Per room policies, supplied when the room is created and sent to peers in
+/// [`crate::message::S2C::Init`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoomSettings {
+    /// Mime types of the codecs the room's router offers, such as `audio/opus` or `video/VP8`.
+    /// Empty offers every codec the server supports. Fixed once the room exists.
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    /// Capped by `MAX_PEERS_PER_ROOM`.
+    #[serde(default)]
+    pub max_peers: Option<usize>,
+    #[serde(default)]
+    pub publish: PublishPolicy,
+    #[serde(default = "enabled")]
+    pub chat: bool,
+    /// Advisory, tells clients whether they may record the room.
+    #[serde(default)]
+    pub recording: bool,
+    /// Required to join, never sent to clients.
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        Self {
+            codecs: Vec::new(),
+            max_peers: None,
+            publish: PublishPolicy::default(),
+            chat: true,
+            recording: false,
+            password: None,
+        }
+    }
+}
+
+impl RoomSettings {
+    /// The subset of `supported` the room offers.
+    pub fn media_codecs(
+        &self,
+        supported: Vec<RtpCodecCapability>,
+    ) -> Result<Vec<RtpCodecCapability>, String> {
+        if self.codecs.is_empty() {
+            return Ok(supported);
+        }
+
+        if let Some(unknown) = self.codecs.iter().find(|codec| {
+            !supported
+                .iter()
+                .any(|supported| mime_type(supported).eq_ignore_ascii_case(codec))
+        }) {
+            return Err(format!("Unsupported codec {unknown:?}"));
+        }
+
+        Ok(supported
+            .into_iter()
+            .filter(|supported| {
+                self.codecs
+                    .iter()
+                    .any(|codec| mime_type(supported).eq_ignore_ascii_case(codec))
+            })
+            .collect())
+    }
+
+    pub fn check_password(&self, given: Option<&str>) -> Result<(), String> {
+        match &self.password {
+            Some(password) if given != Some(password.as_str()) => {
+                Err("Wrong room password".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads `maxPeers: 0` as no peer limit and an empty `password` as no password, the same as
+    /// [`SettingsUpdate`] does.
+    pub fn normalize(&mut self) {
+        self.max_peers = self.max_peers.filter(|&max_peers| max_peers > 0);
+        self.password = self.password.take().filter(|password| !password.is_empty());
+    }
+
+    pub fn apply(&mut self, update: SettingsUpdate) {
+        if let Some(max_peers) = update.max_peers {
+            self.max_peers = Some(max_peers);
+        }
+        if let Some(publish) = update.publish {
+            self.publish = publish;
+        }
+        if let Some(chat) = update.chat {
+            self.chat = chat;
+        }
+        if let Some(recording) = update.recording {
+            self.recording = recording;
+        }
+        if let Some(password) = update.password {
+            self.password = Some(password);
+        }
+        self.normalize();
+    }
+}
+
+/// Changes the host makes to [`RoomSettings`] at runtime, absent fields are left alone.
+/// `maxPeers: 0` lifts the room's peer limit and an empty `password` removes the password.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsUpdate {
+    #[serde(default)]
+    max_peers: Option<usize>,
+    #[serde(default)]
+    publish: Option<PublishPolicy>,
+    #[serde(default)]
+    chat: Option<bool>,
+    #[serde(default)]
+    recording: Option<bool>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+fn mime_type(codec: &RtpCodecCapability) -> &'static str {
+    match codec {
+        RtpCodecCapability::Audio { mime_type, .. } => mime_type.as_str(),
+        RtpCodecCapability::Video { mime_type, .. } => mime_type.as_str(),
+    }
+}
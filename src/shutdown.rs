@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::vcreg::VcRegistry;
+
+#[derive(Debug, Clone)]
+pub struct DrainConfig {
+    /// How long peers get to leave on their own before they are disconnected.
+    pub timeout: Duration,
+    /// Suggested delay before clients reconnect, giving a replacement instance time to come up.
+    pub reconnect_after: Duration,
+}
+
+/// Sent to every peer once the server starts draining.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GoingAway {
+    pub reason: String,
+    pub reconnect_after_ms: u64,
+}
+
+/// Set once the server starts shutting down, new peers are turned away from then on.
+#[derive(Debug, Default)]
+pub struct Drain(AtomicBool);
+
+impl Drain {
+    pub fn start(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Resolves on Ctrl-C or, on Unix, SIGTERM.
+pub async fn signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        futures_lite::future::or(actix_web::rt::signal::ctrl_c(), async {
+            terminate.recv().await;
+            Ok(())
+        })
+        .await
+    }
+    #[cfg(not(unix))]
+    {
+        actix_web::rt::signal::ctrl_c().await
+    }
+}
+
+/// How long disconnected peers get to wind down before the remaining rooms are given up on.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tells every peer the server is going away and waits up to `config.timeout` for them to leave,
+/// disconnecting whoever is left after that. Then closes every room.
+pub async fn drain(vc_registry: &VcRegistry, config: &DrainConfig) {
+    let notice = GoingAway {
+        reason: "Server is shutting down".to_string(),
+        reconnect_after_ms: config.reconnect_after.as_millis() as u64,
+    };
+    let vcs = vc_registry.vcs().await;
+    tracing::info!(rooms = vcs.len(), "Draining");
+    for vc in &vcs {
+        vc.going_away(&notice);
+    }
+    drop(vcs);
+
+    let no_peers = || async { vc_registry.usage().await.1 == 0 };
+    if !wait_until(config.timeout, no_peers).await {
+        let vcs = vc_registry.vcs().await;
+        tracing::warn!(
+            rooms = vcs.len(),
+            "Peers still connected after the drain timeout, disconnecting them"
+        );
+        for vc in &vcs {
+            vc.disconnect_all(&notice.reason);
+        }
+        drop(vcs);
+
+        if !wait_until(CLOSE_TIMEOUT, no_peers).await {
+            tracing::warn!("Peers still connected after disconnecting them");
+        }
+    }
+
+    // Rooms are otherwise kept open for a while after their last peer left
+    for vc in vc_registry.vcs().await {
+        vc.close(&notice.reason);
+    }
+    if !wait_until(CLOSE_TIMEOUT, || vc_registry.is_empty()).await {
+        tracing::warn!("Rooms still open after closing them");
+    }
+}
+
+async fn wait_until<F, Fut>(timeout: Duration, mut done: F) -> bool
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if done().await {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+    }
+}
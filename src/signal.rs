@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::peer::PeerId;
+use crate::ratelimit::{RateLimit, TokenBucket};
+
+/// Largest serialized signal payload accepted, in bytes.
+const MAX_PAYLOAD_SIZE: usize = 1024;
+
+/// Allowed ephemeral signal types and their per peer rate limits. Parsed from a comma separated
+/// list of `<type>=<burst>/<seconds>`, e.g. `"reaction=10/10,applause=3/10"`.
+#[derive(Debug, Clone)]
+pub struct SignalRegistry(HashMap<String, RateLimit>);
+
+impl FromStr for SignalRegistry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|entry| !entry.trim().is_empty())
+            .map(|entry| {
+                let (kind, limit) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected <type>=<rate limit>, got {entry:?}"))?;
+                Ok((kind.trim().to_string(), limit.parse()?))
+            })
+            .collect::<Result<_, String>>()
+            .map(Self)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SignalConfig {
+    pub types: SignalRegistry,
+    pub hand_rate_limit: RateLimit,
+}
+
+/// Rate limiting for ephemeral signals plus the room's raise-hand queue.
+pub struct Signals {
+    config: SignalConfig,
+    rate_limits: HashMap<(PeerId, String), TokenBucket>,
+    hand_rate_limits: HashMap<PeerId, TokenBucket>,
+    hands: Vec<PeerId>,
+}
+
+impl Signals {
+    pub fn new(config: SignalConfig) -> Self {
+        Self {
+            config,
+            rate_limits: HashMap::new(),
+            hand_rate_limits: HashMap::new(),
+            hands: Vec::new(),
+        }
+    }
+
+    /// Checks that `peer_id` may send a `kind` signal with a payload of `payload_size` bytes now.
+    pub fn check(
+        &mut self,
+        peer_id: &PeerId,
+        kind: &str,
+        payload_size: usize,
+    ) -> Result<(), String> {
+        let limit = *self
+            .config
+            .types
+            .0
+            .get(kind)
+            .ok_or_else(|| format!("Unknown signal type {kind:?}"))?;
+        if payload_size > MAX_PAYLOAD_SIZE {
+            return Err(format!(
+                "Signal payload is larger than {MAX_PAYLOAD_SIZE} bytes"
+            ));
+        }
+
+        let allowed = self
+            .rate_limits
+            .entry((peer_id.clone(), kind.to_string()))
+            .or_insert_with(|| TokenBucket::new(limit))
+            .try_take();
+        if allowed {
+            Ok(())
+        } else {
+            Err(format!("Sending {kind:?} signals too fast"))
+        }
+    }
+
+    /// Queues `peer_id`, returns `false` if its hand was already raised.
+    pub fn raise_hand(&mut self, peer_id: &PeerId) -> Result<bool, String> {
+        self.check_hand_rate_limit(peer_id)?;
+        if self.hands.contains(peer_id) {
+            return Ok(false);
+        }
+        self.hands.push(peer_id.clone());
+
+        Ok(true)
+    }
+
+    /// Removes `peer_id` from the queue, returns `false` if its hand was not raised.
+    pub fn lower_hand(&mut self, peer_id: &PeerId) -> bool {
+        let len = self.hands.len();
+        self.hands.retain(|p| p != peer_id);
+
+        self.hands.len() != len
+    }
+
+    pub fn clear_hands(&mut self) {
+        self.hands.clear();
+    }
+
+    /// Raised hands, in the order they were raised.
+    pub fn hands(&self) -> Vec<PeerId> {
+        self.hands.clone()
+    }
+
+    /// Drops everything kept about a departed peer, returns `true` if its hand was lowered.
+    pub fn forget_peer(&mut self, peer_id: &PeerId) -> bool {
+        self.rate_limits.retain(|(p, _), _| p != peer_id);
+        self.hand_rate_limits.remove(peer_id);
+        self.lower_hand(peer_id)
+    }
+
+    fn check_hand_rate_limit(&mut self, peer_id: &PeerId) -> Result<(), String> {
+        let limit = self.config.hand_rate_limit;
+        let allowed = self
+            .hand_rate_limits
+            .entry(peer_id.clone())
+            .or_insert_with(|| TokenBucket::new(limit))
+            .try_take();
+
+        if allowed {
+            Ok(())
+        } else {
+            Err("Raising hand too fast".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn signals() -> Signals {
+        Signals::new(SignalConfig {
+            types: "reaction=2/60".parse().unwrap(),
+            hand_rate_limit: RateLimit::new(10, Duration::from_secs(60)),
+        })
+    }
+
+    #[test]
+    fn parses_registry() {
+        let registry: SignalRegistry = " reaction=10/10, applause=3/5 ,".parse().unwrap();
+        assert_eq!(registry.0.len(), 2);
+        assert_eq!(registry.0["reaction"].burst, 10);
+        assert_eq!(registry.0["applause"].period, Duration::from_secs(5));
+
+        assert!("".parse::<SignalRegistry>().unwrap().0.is_empty());
+        assert!("reaction".parse::<SignalRegistry>().is_err());
+        assert!("reaction=10".parse::<SignalRegistry>().is_err());
+    }
+
+    #[test]
+    fn checks_type_size_and_rate() {
+        let mut signals = signals();
+        let alice = PeerId::from("alice".to_string());
+        let bob = PeerId::from("bob".to_string());
+
+        assert!(signals.check(&alice, "applause", 0).is_err());
+        assert!(signals
+            .check(&alice, "reaction", MAX_PAYLOAD_SIZE + 1)
+            .is_err());
+        assert!(signals.check(&alice, "reaction", MAX_PAYLOAD_SIZE).is_ok());
+        assert!(signals.check(&alice, "reaction", 0).is_ok());
+        assert!(signals.check(&alice, "reaction", 0).is_err());
+        // Limits are per peer
+        assert!(signals.check(&bob, "reaction", 0).is_ok());
+    }
+
+    #[test]
+    fn queues_hands_in_order() {
+        let mut signals = signals();
+        let alice = PeerId::from("alice".to_string());
+        let bob = PeerId::from("bob".to_string());
+
+        assert_eq!(signals.raise_hand(&alice), Ok(true));
+        assert_eq!(signals.raise_hand(&bob), Ok(true));
+        assert_eq!(signals.raise_hand(&alice), Ok(false));
+        assert_eq!(signals.hands(), vec![alice.clone(), bob.clone()]);
+
+        assert!(signals.forget_peer(&alice));
+        assert!(!signals.forget_peer(&alice));
+        assert_eq!(signals.hands(), vec![bob.clone()]);
+        assert!(!signals.lower_hand(&alice));
+        assert!(signals.lower_hand(&bob));
+        assert!(signals.hands().is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use mediasoup::consumer::{ConsumerScore, ConsumerStats};
+use mediasoup::prelude::*;
+use mediasoup::producer::{ProducerScore, ProducerStat};
+use mediasoup::webrtc_transport::WebRtcTransportStat;
+use serde::{Deserialize, Serialize};
+
+/// A mediasoup object to fetch stats for.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum StatsTarget {
+    Producer { id: ProducerId },
+    Consumer { id: ConsumerId },
+    Transport { id: TransportId },
+}
+
+impl StatsTarget {
+    /// Parses the `{kind}/{id}` pair used by the admin API, `kind` being the plural type name.
+    pub fn parse(kind: &str, id: &str) -> Result<Self, String> {
+        let invalid_id = |error| format!("Invalid id {id:?}: {error}");
+        match kind {
+            "producers" => Ok(Self::Producer {
+                id: id.parse().map_err(invalid_id)?,
+            }),
+            "consumers" => Ok(Self::Consumer {
+                id: id.parse().map_err(invalid_id)?,
+            }),
+            "transports" => Ok(Self::Transport {
+                id: id.parse().map_err(invalid_id)?,
+            }),
+            _ => Err(format!("Unknown stats target {kind:?}")),
+        }
+    }
+}
+
+/// Raw mediasoup stats, including bitrates, packet loss, jitter, RTT and scores.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Stats {
+    Producer(Vec<ProducerStat>),
+    Consumer(Box<ConsumerStats>),
+    Transport(Vec<WebRtcTransportStat>),
+}
+
+/// The object a [`StatsTarget`] resolved to.
+pub enum StatsSource {
+    Producer(Producer),
+    Consumer(Consumer),
+    Transport(WebRtcTransport),
+}
+
+impl StatsSource {
+    pub async fn get_stats(&self) -> Result<Stats, String> {
+        let stats = match self {
+            StatsSource::Producer(producer) => producer.get_stats().await.map(Stats::Producer),
+            StatsSource::Consumer(consumer) => consumer
+                .get_stats()
+                .await
+                .map(|stats| Stats::Consumer(Box::new(stats))),
+            StatsSource::Transport(transport) => transport.get_stats().await.map(Stats::Transport),
+        };
+
+        stats.map_err(|error| format!("Failed to get stats: {error}"))
+    }
+}
+
+/// Latest score of one of a peer's producers, one entry per RTP stream.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProducerScores {
+    pub id: ProducerId,
+    pub score: Vec<ProducerScore>,
+}
+
+/// Latest score of one of a peer's consumers.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsumerScores {
+    pub id: ConsumerId,
+    pub score: ConsumerScore,
+}
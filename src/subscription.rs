@@ -0,0 +1,49 @@
+use std::collections::HashSet;
+
+use mediasoup::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::peer::PeerId;
+use crate::producer::ProducerSource;
+
+/// What a peer wants to receive, anything not allowed is paused server side. Unset fields allow
+/// everything.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionRules {
+    /// Only receive media from these peers.
+    pub peers: Option<HashSet<PeerId>>,
+    /// Only receive these kinds, `["audio"]` for audio-only.
+    pub kinds: Option<HashSet<MediaKind>>,
+    /// Only receive these sources, e.g. `["screen"]` for just the screen share.
+    pub sources: Option<HashSet<ProducerSource>>,
+}
+
+impl SubscriptionRules {
+    pub fn allows(&self, peer_id: &PeerId, kind: MediaKind, source: ProducerSource) -> bool {
+        self.peers
+            .as_ref()
+            .is_none_or(|peers| peers.contains(peer_id))
+            && self
+                .kinds
+                .as_ref()
+                .is_none_or(|kinds| kinds.contains(&kind))
+            && self
+                .sources
+                .as_ref()
+                .is_none_or(|sources| sources.contains(&source))
+    }
+}
+
+/// Picks the `n` peers whose video gets forwarded: the most recent speakers among `candidates`.
+pub fn last_n<'a>(
+    speakers: &'a [PeerId],
+    candidates: &HashSet<&PeerId>,
+    n: usize,
+) -> HashSet<&'a PeerId> {
+    speakers
+        .iter()
+        .filter(|peer_id| candidates.contains(peer_id))
+        .take(n)
+        .collect()
+}
@@ -0,0 +1,141 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use parking_lot::RwLock;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{Certificate, PrivateKey, ServerConfig};
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM encoded PKCS#8, RSA or EC private key.
+    pub key_path: PathBuf,
+    /// How often the files are checked for changes, `None` disables reloading.
+    pub reload_interval: Option<Duration>,
+}
+
+/// Serves the most recently loaded certificate, so renewed certificates are picked up without a
+/// restart.
+pub struct CertResolver {
+    config: TlsConfig,
+    key: RwLock<Loaded>,
+}
+
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl CertResolver {
+    pub fn new(config: TlsConfig) -> Result<Arc<Self>, String> {
+        let modified = modified(&config);
+        let key = load(&config)?;
+
+        Ok(Arc::new(Self {
+            config,
+            key: RwLock::new(Loaded { key, modified }),
+        }))
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::clone(self) as Arc<dyn ResolvesServerCert>)
+    }
+
+    /// Periodically reloads the certificate and key once either file changed. A pair that fails
+    /// to load is logged and the previous one kept.
+    pub fn watch(self: &Arc<Self>) {
+        let Some(interval) = self.config.reload_interval else {
+            return;
+        };
+        let resolver = Arc::downgrade(self);
+
+        actix_web::rt::spawn(async move {
+            loop {
+                actix_web::rt::time::sleep(interval).await;
+                let Some(resolver) = resolver.upgrade() else {
+                    break;
+                };
+                resolver.reload_if_changed();
+            }
+        });
+    }
+
+    fn reload_if_changed(&self) {
+        let modified = modified(&self.config);
+        if modified == self.key.read().modified {
+            return;
+        }
+
+        match load(&self.config) {
+            Ok(key) => {
+                *self.key.write() = Loaded { key, modified };
+                tracing::info!("Reloaded TLS certificate");
+            }
+            Err(error) => {
+                tracing::error!(
+                    "Failed to reload TLS certificate, keeping the previous one: {error}"
+                );
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.key.read().key))
+    }
+}
+
+fn modified(config: &TlsConfig) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    (modified(&config.cert_path), modified(&config.key_path))
+}
+
+fn load(config: &TlsConfig) -> Result<Arc<CertifiedKey>, String> {
+    let read_pem = |path: &PathBuf| {
+        File::open(path)
+            .and_then(|file| rustls_pemfile::read_all(&mut BufReader::new(file)))
+            .map_err(|error| format!("Failed to read {}: {error}", path.display()))
+    };
+
+    let certs = read_pem(&config.cert_path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            rustls_pemfile::Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(format!(
+            "No certificate found in {}",
+            config.cert_path.display()
+        ));
+    }
+
+    let key = read_pem(&config.key_path)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", config.key_path.display()))?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|error| format!("Unsupported private key: {error}"))?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, key)))
+}
@@ -1,40 +1,164 @@
-use std::{collections::HashMap, sync::Arc, sync::Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, sync::Weak};
 
+use actix::Recipient;
 use event_listener_primitives::{Bag, BagOnce, HandlerId};
-use mediasoup::{
-    prelude::*,
-    worker::{WorkerLogLevel, WorkerLogTag},
-};
+use mediasoup::prelude::*;
+use mediasoup::webrtc_transport::WeakWebRtcTransport;
 use parking_lot::Mutex;
 use serde::Serialize;
+use tracing::Span;
 
 use crate::{
-    message::{Notification, NotificationType},
-    peer::PeerId,
+    bandwidth::RoomBudget,
+    chat::{Chat, ChatEvent, ChatMessage},
+    clock,
+    config::Config,
+    message::{DebugState, Notification, NotificationType, StampedNotification},
+    peer::{PeerId, PeerMetadata, SessionId},
+    playback::{PlaybackCommand, PlaybackState},
+    producer::ProducerInfo,
+    quality::NetworkQuality,
+    settings::{PublishPolicy, RoomSettings, SettingsUpdate},
+    shutdown::GoingAway,
+    signal::Signals,
+    stats::{StatsSource, StatsTarget},
+    workers::{PooledWorker, WorkerPool},
 };
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Hash)]
 pub struct VcId(pub String);
 
+impl fmt::Display for VcId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// What to do when a peer joins with a [`PeerId`] that is already connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePeerPolicy {
+    /// Refuse the new connection.
+    Reject,
+    /// Accept the new connection and kick the old one.
+    Replace,
+    /// Accept both, the new session gets its own `user#session` peer id.
+    MultiDevice,
+}
+
+impl FromStr for DuplicatePeerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "replace" => Ok(Self::Replace),
+            "multi-device" => Ok(Self::MultiDevice),
+            _ => Err(format!("Unknown duplicate peer policy {s:?}")),
+        }
+    }
+}
+
+/// Last known presence of a peer, kept so late joiners see accurate state immediately.
+#[derive(Clone, Default)]
+pub struct Presence {
+    pub status: Option<NotificationType>,
+    pub quality: Option<NetworkQuality>,
+}
+
+impl Presence {
+    /// Notifications that bring a newcomer up to date with this presence.
+    pub fn notifications(&self, peer_id: &PeerId) -> Vec<Notification> {
+        self.status
+            .iter()
+            .map(|status| status.notification(peer_id.clone()))
+            .chain(self.quality.map(|quality| Notification::NetworkQuality {
+                peer_id: peer_id.clone(),
+                quality,
+            }))
+            .collect()
+    }
+}
+
+struct Client {
+    session_id: SessionId,
+    metadata: PeerMetadata,
+    producers: Vec<Producer>,
+    /// Owned by the peer's connection, tracked for stats lookups.
+    transports: Vec<WeakWebRtcTransport>,
+    consumers: Vec<WeakConsumer>,
+    presence: Presence,
+    /// The peer's connection, queried for the debug dump.
+    connection: Option<Recipient<DebugState>>,
+}
+
+impl Client {
+    fn new(session_id: SessionId, metadata: PeerMetadata) -> Self {
+        Self {
+            session_id,
+            metadata,
+            producers: Vec::new(),
+            transports: Vec::new(),
+            consumers: Vec::new(),
+            presence: Presence::default(),
+            connection: None,
+        }
+    }
+}
+
 #[derive(Default)]
+#[allow(clippy::type_complexity)]
 struct Handlers {
-    notification: Bag<Arc<dyn Fn(&Notification) + Send + Sync>, Notification>,
+    notification: Bag<Arc<dyn Fn(&StampedNotification) + Send + Sync>, StampedNotification>,
     producer_add: Bag<Arc<dyn Fn(&PeerId, &Producer) + Send + Sync>, PeerId, Producer>,
     producer_remove: Bag<Arc<dyn Fn(&PeerId, &ProducerId) + Send + Sync>, PeerId, ProducerId>,
-    echo: Bag<Arc<dyn Fn(&PeerId, &String) + Send + Sync>, PeerId, String>,
+    chat: Bag<Arc<dyn Fn(&ChatEvent) + Send + Sync>, ChatEvent>,
+    playback: Bag<Arc<dyn Fn(&PlaybackState) + Send + Sync>, PlaybackState>,
+    kick: Bag<Arc<dyn Fn(&PeerId, &SessionId, &String) + Send + Sync>, PeerId, SessionId, String>,
+    speakers: Bag<Arc<dyn Fn(&Vec<PeerId>) + Send + Sync>, Vec<PeerId>>,
+    layer_cap: Bag<Arc<dyn Fn(&Option<u8>) + Send + Sync>, Option<u8>>,
+    going_away: Bag<Arc<dyn Fn(&GoingAway) + Send + Sync>, GoingAway>,
+    disconnect: Bag<Arc<dyn Fn(&String) + Send + Sync>, String>,
+    room_closed: Bag<Arc<dyn Fn(&String) + Send + Sync>, String>,
+    settings: Bag<Arc<dyn Fn(&RoomSettings) + Send + Sync>, RoomSettings>,
     close: BagOnce<Box<dyn FnOnce() + Send>>,
 }
 
 pub struct VcInner {
     id: VcId,
+    /// Parent of every peer's span, carries `vc_id`.
+    span: Span,
     router: Router,
+    webrtc_server: Option<WebRtcServer>,
+    config: Arc<Config>,
+    settings: Mutex<RoomSettings>,
     handlers: Handlers,
-    clients: Mutex<HashMap<PeerId, Vec<Producer>>>,
+    clients: Mutex<HashMap<PeerId, Client>>,
+    host: Mutex<Option<PeerId>>,
+    /// Replaced sessions and why, kept until their connection has seen the kick or is gone.
+    kicked: Mutex<HashMap<SessionId, String>>,
+    playback: Mutex<PlaybackState>,
+    chat: Mutex<Chat>,
+    signals: Mutex<Signals>,
+    active_speaker_observer: ActiveSpeakerObserver,
+    /// Every peer, most recent dominant speaker first and the rest in join order.
+    speakers: Mutex<Vec<PeerId>>,
+    _dominant_speaker_handler: HandlerId,
+    /// Present when the room has an outgoing bitrate budget.
+    budget: Option<Mutex<RoomBudget>>,
+    /// How long the room stays open after losing its last peer.
+    empty_timeout: Duration,
+    /// When the room last lost its last peer, `None` while it has peers.
+    emptied_at: Mutex<Option<Instant>>,
+    /// Holds the room open while it has no peers, until its empty timeout passes or it is closed.
+    keep_alive: Mutex<Option<Vc>>,
+    closed: AtomicBool,
 }
 
 impl Drop for VcInner {
     fn drop(&mut self) {
-        dbg!("Vc {:?} closed", self.id.clone());
+        tracing::info!(parent: &self.span, "Vc closed");
         self.handlers.close.call_simple();
     }
 }
@@ -45,108 +169,833 @@ pub struct Vc {
 }
 
 impl Vc {
-    pub async fn new(worker_manager: &WorkerManager, id: VcId) -> Result<Self, String> {
-        let worker = worker_manager
-            .create_worker({
-                let mut settings = WorkerSettings::default();
-                settings.log_level = WorkerLogLevel::Debug;
-                settings.log_tags = vec![
-                    WorkerLogTag::Info,
-                    WorkerLogTag::Ice,
-                    WorkerLogTag::Dtls,
-                    WorkerLogTag::Rtp,
-                    WorkerLogTag::Srtp,
-                    WorkerLogTag::Rtcp,
-                    WorkerLogTag::Rtx,
-                    WorkerLogTag::Bwe,
-                    WorkerLogTag::Score,
-                    WorkerLogTag::Simulcast,
-                    WorkerLogTag::Svc,
-                    WorkerLogTag::Sctp,
-                    WorkerLogTag::Message,
-                ];
-
-                settings
-            })
-            .await
-            .map_err(|error| format!("Failed to create worker: {error}"))?;
+    /// Least time a room that never had a peer stays open, so that rooms created ahead of their
+    /// first join survive an `empty_timeout` of zero.
+    const NEW_ROOM_TIMEOUT: Duration = Duration::from_secs(30);
 
+    /// Creates an empty room, which stays open for `empty_timeout`, but at least
+    /// [`Self::NEW_ROOM_TIMEOUT`], unless a peer joins.
+    pub async fn new(
+        workers: &WorkerPool,
+        id: VcId,
+        config: Arc<Config>,
+        empty_timeout: Duration,
+        settings: RoomSettings,
+    ) -> Result<Self, String> {
+        let PooledWorker {
+            worker,
+            webrtc_server,
+            ..
+        } = workers.pick()?;
         let router = worker
-            .create_router(RouterOptions::new(crate::media_codecs()))
+            .create_router(RouterOptions::new(
+                settings.media_codecs(crate::media_codecs())?,
+            ))
             .await
             .map_err(|error| format!("Failed to create router: {error}"))?;
 
-        println!("Vc {id:?} created");
+        let active_speaker_observer = router
+            .create_active_speaker_observer(ActiveSpeakerObserverOptions::default())
+            .await
+            .map_err(|error| format!("Failed to create active speaker observer: {error}"))?;
+
+        let chat = Chat::open(&id, config.chat.clone())?;
+        let signals = Signals::new(config.signals.clone());
+        let budget = config
+            .bandwidth
+            .room_budget
+            .map(|budget| Mutex::new(RoomBudget::new(budget, config.bandwidth.sample_interval)));
+
+        let span = tracing::info_span!("vc", vc_id = %id);
+        tracing::info!(parent: &span, "Vc created");
+
+        let vc = Self {
+            inner: Arc::new_cyclic(|inner_weak| {
+                let dominant_speaker_handler = active_speaker_observer.on_dominant_speaker({
+                    let vc = WeakVc {
+                        inner: inner_weak.clone(),
+                    };
 
-        Ok(Self {
-            inner: Arc::new(VcInner {
-                id,
-                router,
-                handlers: Handlers::default(),
-                clients: Mutex::default(),
+                    move |dominant_speaker| {
+                        if let Some(vc) = vc.upgrade() {
+                            vc.set_dominant_speaker(&dominant_speaker.producer.id());
+                        }
+                    }
+                });
+
+                VcInner {
+                    id,
+                    span,
+                    router,
+                    webrtc_server,
+                    config,
+                    settings: Mutex::new(settings),
+                    handlers: Handlers::default(),
+                    clients: Mutex::default(),
+                    host: Mutex::default(),
+                    kicked: Mutex::default(),
+                    playback: Mutex::new(PlaybackState::new(clock::now_millis())),
+                    chat: Mutex::new(chat),
+                    signals: Mutex::new(signals),
+                    active_speaker_observer,
+                    speakers: Mutex::default(),
+                    _dominant_speaker_handler: dominant_speaker_handler,
+                    budget,
+                    empty_timeout,
+                    emptied_at: Mutex::default(),
+                    keep_alive: Mutex::default(),
+                    closed: AtomicBool::new(false),
+                }
             }),
-        })
+        };
+        vc.linger(empty_timeout.max(Self::NEW_ROOM_TIMEOUT));
+
+        Ok(vc)
     }
 
     pub fn id(&self) -> VcId {
         self.inner.id.clone()
     }
 
+    pub fn span(&self) -> &Span {
+        &self.inner.span
+    }
+
     pub fn router(&self) -> &Router {
         &self.inner.router
     }
 
-    pub fn add_peer(&self, peer_id: PeerId) {
+    /// Options for a new WebRTC transport, going through the worker's WebRtcServer in
+    /// single-port mode.
+    pub fn webrtc_transport_options(&self) -> WebRtcTransportOptions {
+        let rtc = &self.inner.config.rtc;
+        let mut options = match &self.inner.webrtc_server {
+            Some(webrtc_server) => WebRtcTransportOptions::new_with_server(webrtc_server.clone()),
+            None => WebRtcTransportOptions::new(rtc.listen.webrtc_transport_listen_infos()),
+        };
+        options.prefer_udp = rtc.prefer_udp;
+        options.prefer_tcp = rtc.prefer_tcp;
+
+        options
+    }
+
+    /// The peer allowed to drive room wide state such as playback.
+    pub fn host(&self) -> Option<PeerId> {
+        self.inner.host.lock().clone()
+    }
+
+    pub fn is_host(&self, peer_id: &PeerId) -> bool {
+        self.inner.host.lock().as_ref() == Some(peer_id)
+    }
+
+    /// Registers a new session for `peer_id`, resolving clashes with an already connected session
+    /// according to the configured [`DuplicatePeerPolicy`]. Returns the peer id the session was
+    /// registered under.
+    pub fn add_peer(
+        &self,
+        peer_id: PeerId,
+        session_id: SessionId,
+        metadata: PeerMetadata,
+    ) -> Result<PeerId, String> {
+        metadata.validate()?;
+        if self.is_closed() {
+            return Err("Room is closed".to_string());
+        }
+
+        let mut clients = self.inner.clients.lock();
+
+        let max_peers = [
+            self.inner.settings.lock().max_peers,
+            self.inner.config.capacity.max_peers_per_room,
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(max_peers) = max_peers {
+            // A replaced session frees its slot
+            let replaces = clients.contains_key(&peer_id)
+                && self.inner.config.duplicate_peer_policy == DuplicatePeerPolicy::Replace;
+            if !replaces && clients.len() >= max_peers {
+                return Err(format!("Room is full, it allows at most {max_peers} peers"));
+            }
+        }
+
+        let peer_id = match clients.get(&peer_id) {
+            None => peer_id,
+            Some(_) => match self.inner.config.duplicate_peer_policy {
+                DuplicatePeerPolicy::Reject => {
+                    return Err(format!("Peer {peer_id:?} is already connected"));
+                }
+                // The old session is displaced by the insert below
+                DuplicatePeerPolicy::Replace => peer_id,
+                DuplicatePeerPolicy::MultiDevice => peer_id.with_session(session_id),
+            },
+        };
+
+        // Swapped under the same lock, so no other session can slip in between
+        let replaced = clients.insert(peer_id.clone(), Client::new(session_id, metadata.clone()));
+        drop(clients);
+
+        if let Some(old) = replaced {
+            let old_session_id = old.session_id;
+            let reason = "Replaced by a newer session".to_string();
+            // The old connection may still be starting up and miss the kick below
+            self.inner
+                .kicked
+                .lock()
+                .insert(old_session_id, reason.clone());
+            self.inner
+                .handlers
+                .kick
+                .call_simple(&peer_id, &old_session_id, &reason);
+            self.remove_client(&peer_id, old);
+        }
+
+        self.inner.emptied_at.lock().take();
+        let keep_alive = self.inner.keep_alive.lock().take();
+        drop(keep_alive);
+
+        let speakers = {
+            let mut speakers = self.inner.speakers.lock();
+            if !speakers.contains(&peer_id) {
+                speakers.push(peer_id.clone());
+            }
+            speakers.clone()
+        };
+        self.inner.handlers.speakers.call_simple(&speakers);
+
+        self.emit(Notification::PeerJoin {
+            peer_id: peer_id.clone(),
+            metadata,
+        });
+
+        let became_host = {
+            let mut host = self.inner.host.lock();
+            if host.is_none() {
+                *host = Some(peer_id.clone());
+                true
+            } else {
+                false
+            }
+        };
+        if became_host {
+            self.emit(Notification::HostChange {
+                peer_id: peer_id.clone(),
+            });
+        }
+
+        Ok(peer_id)
+    }
+
+    pub fn update_metadata(&self, peer_id: &PeerId, metadata: PeerMetadata) -> Result<(), String> {
+        metadata.validate()?;
+
+        match self.inner.clients.lock().get_mut(peer_id) {
+            Some(client) => client.metadata = metadata.clone(),
+            None => return Ok(()),
+        }
+
+        self.emit(Notification::PeerUpdate {
+            peer_id: peer_id.clone(),
+            metadata,
+        });
+
+        Ok(())
+    }
+
+    /// Applies a playback command from `peer_id`, only the host may control playback.
+    pub fn playback(&self, peer_id: &PeerId, command: PlaybackCommand) -> Result<(), String> {
+        if !self.is_host(peer_id) {
+            return Err("Only the host can control playback".to_string());
+        }
+
+        let state = {
+            let mut playback = self.inner.playback.lock();
+            playback.apply(command, clock::now_millis())?;
+            playback.clone()
+        };
+
+        self.inner.handlers.playback.call_simple(&state);
+
+        Ok(())
+    }
+
+    pub fn playback_state(&self) -> PlaybackState {
+        self.inner.playback.lock().clone()
+    }
+
+    /// The room's current settings, including its password.
+    pub fn settings(&self) -> RoomSettings {
+        self.inner.settings.lock().clone()
+    }
+
+    /// Host only, the new settings are announced to every peer.
+    pub fn update_settings(&self, peer_id: &PeerId, update: SettingsUpdate) -> Result<(), String> {
+        if !self.is_host(peer_id) {
+            return Err("Only the host can change the room settings".to_string());
+        }
+
+        let settings = {
+            let mut settings = self.inner.settings.lock();
+            settings.apply(update);
+            settings.clone()
+        };
+        tracing::info!(parent: self.span(), "Settings changed");
+        self.inner.handlers.settings.call_simple(&settings);
+
+        Ok(())
+    }
+
+    pub fn check_password(&self, given: Option<&str>) -> Result<(), String> {
+        self.inner.settings.lock().check_password(given)
+    }
+
+    /// Whether the room's [`PublishPolicy`] lets `peer_id` publish.
+    pub fn can_publish(&self, peer_id: &PeerId) -> Result<(), String> {
+        match self.inner.settings.lock().publish {
+            PublishPolicy::Participants => Ok(()),
+            PublishPolicy::HostOnly if self.is_host(peer_id) => Ok(()),
+            PublishPolicy::HostOnly => Err("Only the host can publish in this room".to_string()),
+        }
+    }
+
+    fn check_chat(&self) -> Result<(), String> {
+        if self.inner.settings.lock().chat {
+            Ok(())
+        } else {
+            Err("Chat is disabled in this room".to_string())
+        }
+    }
+
+    /// Posts a chat message, returning its id.
+    pub fn chat_send(&self, peer_id: &PeerId, text: String) -> Result<u64, String> {
+        self.check_chat()?;
+        let event = self.inner.chat.lock().send(peer_id, text)?;
+        let id = match &event {
+            ChatEvent::Message(message) => message.id,
+            _ => unreachable!("Chat::send always returns a message"),
+        };
+
+        self.inner.handlers.chat.call_simple(&event);
+
+        Ok(id)
+    }
+
+    pub fn chat_edit(&self, peer_id: &PeerId, id: u64, text: String) -> Result<(), String> {
+        self.check_chat()?;
+        let event = self.inner.chat.lock().edit(peer_id, id, text)?;
+        self.inner.handlers.chat.call_simple(&event);
+
+        Ok(())
+    }
+
+    /// Deletes a chat message, the host may delete anyone's.
+    pub fn chat_delete(&self, peer_id: &PeerId, id: u64) -> Result<(), String> {
+        let moderator = self.is_host(peer_id);
+        let event = self.inner.chat.lock().delete(peer_id, id, moderator)?;
+        self.inner.handlers.chat.call_simple(&event);
+
+        Ok(())
+    }
+
+    pub fn chat_history(&self) -> Vec<ChatMessage> {
+        self.inner.chat.lock().history()
+    }
+
+    /// Fans out an ephemeral signal of one of the registered types.
+    pub fn signal(
+        &self,
+        peer_id: &PeerId,
+        signal: String,
+        payload: Option<serde_json::Value>,
+    ) -> Result<(), String> {
+        let payload_size = payload
+            .as_ref()
+            .map_or(0, |payload| payload.to_string().len());
         self.inner
-            .clients
+            .signals
             .lock()
-            .entry(peer_id.clone())
-            .or_default();
+            .check(peer_id, &signal, payload_size)?;
+
+        self.emit(Notification::Signal {
+            peer_id: peer_id.clone(),
+            signal,
+            payload,
+        });
+
+        Ok(())
+    }
+
+    pub fn raise_hand(&self, peer_id: &PeerId) -> Result<(), String> {
+        if self.inner.signals.lock().raise_hand(peer_id)? {
+            self.emit(Notification::HandRaise {
+                peer_id: peer_id.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Lowers the hand of `target`, or of `peer_id` itself. Only the host may lower other hands.
+    pub fn lower_hand(&self, peer_id: &PeerId, target: Option<PeerId>) -> Result<(), String> {
+        let target = target.unwrap_or_else(|| peer_id.clone());
+        if &target != peer_id && !self.is_host(peer_id) {
+            return Err("Only the host can lower other hands".to_string());
+        }
+
+        if self.inner.signals.lock().lower_hand(&target) {
+            self.emit(Notification::HandLower { peer_id: target });
+        }
+
+        Ok(())
+    }
+
+    pub fn clear_hands(&self, peer_id: &PeerId) -> Result<(), String> {
+        if !self.is_host(peer_id) {
+            return Err("Only the host can clear raised hands".to_string());
+        }
+
+        self.inner.signals.lock().clear_hands();
+        self.emit(Notification::HandsClear);
+
+        Ok(())
+    }
+
+    /// Raised hands, in the order they were raised.
+    pub fn hands(&self) -> Vec<PeerId> {
+        self.inner.signals.lock().hands()
+    }
+
+    pub fn notify(&self, peer_id: &PeerId, notification: &NotificationType) {
+        match self.inner.clients.lock().get_mut(peer_id) {
+            Some(client) => client.presence.status = Some(*notification),
+            None => return,
+        }
+
+        self.emit(notification.notification(peer_id.clone()));
+    }
+
+    pub fn set_network_quality(&self, peer_id: &PeerId, quality: NetworkQuality) {
+        match self.inner.clients.lock().get_mut(peer_id) {
+            Some(client) => client.presence.quality = Some(quality),
+            None => return,
+        }
+
+        self.emit(Notification::NetworkQuality {
+            peer_id: peer_id.clone(),
+            quality,
+        });
+    }
 
+    /// Fans `notification` out to every peer, stamped with the current server time.
+    fn emit(&self, notification: Notification) {
         self.inner
             .handlers
             .notification
-            .call_simple(&Notification::PeerJoin { peer_id });
+            .call_simple(&notification.stamp(clock::now_millis()));
     }
 
-    pub fn echo(&self, peer_id: &PeerId, text: &String) {
-        self.inner.handlers.echo.call_simple(peer_id, text);
+    /// Registers `producer`, enforcing the per room limits on its [`ProducerInfo::source`].
+    pub async fn add_producer(&self, peer_id: PeerId, producer: Producer) -> Result<(), String> {
+        self.can_publish(&peer_id)?;
+        let source = ProducerInfo::of(&producer).source;
+        {
+            let mut clients = self.inner.clients.lock();
+
+            if let Some(limit) = self.inner.config.source_limits.get(source) {
+                let count = clients
+                    .values()
+                    .flat_map(|client| &client.producers)
+                    .filter(|producer| ProducerInfo::of(producer).source == source)
+                    .count();
+                if count >= limit {
+                    return Err(format!(
+                        "This room allows at most {limit} {source:?} producer(s)"
+                    ));
+                }
+            }
+
+            let Some(client) = clients.get_mut(&peer_id) else {
+                return Err(format!("Peer {peer_id:?} is not in this room"));
+            };
+            let kind = producer.kind();
+            if let Some(limit) = self.inner.config.capacity.max_producers_per_peer.get(kind) {
+                let count = client
+                    .producers
+                    .iter()
+                    .filter(|producer| producer.kind() == kind)
+                    .count();
+                if count >= limit {
+                    return Err(format!(
+                        "A peer may have at most {limit} {kind:?} producer(s)"
+                    ));
+                }
+            }
+            client.producers.push(producer.clone());
+        }
+
+        self.inner
+            .handlers
+            .producer_add
+            .call_simple(&peer_id, &producer);
+
+        if producer.kind() == MediaKind::Audio {
+            let options = RtpObserverAddProducerOptions::new(producer.id());
+            if let Err(error) = self
+                .inner
+                .active_speaker_observer
+                .add_producer(options)
+                .await
+            {
+                tracing::warn!(
+                    parent: &self.inner.span,
+                    %peer_id,
+                    producer_id = %producer.id(),
+                    "Failed to observe audio producer: {error}"
+                );
+            }
+        }
+
+        Ok(())
     }
 
-    pub fn notify(&self, peer_id: &PeerId, notification: &NotificationType) {
-        let notification = match notification {
-            NotificationType::Loading => Notification::Loading {
-                peer_id: peer_id.clone(),
-            },
-            NotificationType::Playing => Notification::Playing {
-                peer_id: peer_id.clone(),
-            },
-            NotificationType::Idle => Notification::Idle {
-                peer_id: peer_id.clone(),
-            },
-        };
+    pub fn add_transport(&self, peer_id: &PeerId, transport: &WebRtcTransport) {
+        if let Some(client) = self.inner.clients.lock().get_mut(peer_id) {
+            client.transports.push(transport.downgrade());
+        }
+    }
+
+    pub fn add_consumer(&self, peer_id: &PeerId, consumer: &Consumer) {
+        if let Some(client) = self.inner.clients.lock().get_mut(peer_id) {
+            client
+                .consumers
+                .retain(|consumer| consumer.upgrade().is_some());
+            client.consumers.push(consumer.downgrade());
+        }
+    }
+
+    pub fn set_debug_recipient(
+        &self,
+        peer_id: &PeerId,
+        session_id: SessionId,
+        recipient: Recipient<DebugState>,
+    ) {
+        if let Some(client) = self.inner.clients.lock().get_mut(peer_id) {
+            if client.session_id == session_id {
+                client.connection = Some(recipient);
+            }
+        }
+    }
+
+    /// mediasoup `dump()` output of the router and of every peer's transports, producers and
+    /// consumers, alongside the room's and each connection's own state.
+    pub async fn debug_dump(&self) -> Result<serde_json::Value, String> {
+        let router = self
+            .inner
+            .router
+            .dump()
+            .await
+            .map_err(|error| format!("Failed to dump router: {error}"))?;
+
+        let clients = self
+            .inner
+            .clients
+            .lock()
+            .iter()
+            .map(|(peer_id, client)| {
+                (
+                    peer_id.clone(),
+                    client.session_id,
+                    client.metadata.clone(),
+                    client.producers.clone(),
+                    client
+                        .transports
+                        .iter()
+                        .filter_map(WeakWebRtcTransport::upgrade)
+                        .collect::<Vec<_>>(),
+                    client
+                        .consumers
+                        .iter()
+                        .filter_map(WeakConsumer::upgrade)
+                        .collect::<Vec<_>>(),
+                    client.connection.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Objects may close while being dumped, their errors end up in the document
+        fn dumped<T: Serialize, E: std::fmt::Display>(result: Result<T, E>) -> serde_json::Value {
+            match result {
+                Ok(dump) => serde_json::to_value(dump).unwrap_or_default(),
+                Err(error) => serde_json::json!({ "error": error.to_string() }),
+            }
+        }
+
+        let mut peers = Vec::with_capacity(clients.len());
+        for (peer_id, session_id, metadata, producers, transports, consumers, connection) in clients
+        {
+            let mut producer_dumps = Vec::with_capacity(producers.len());
+            for producer in &producers {
+                producer_dumps.push(serde_json::json!({
+                    "info": ProducerInfo::of(producer),
+                    "dump": dumped(producer.dump().await),
+                }));
+            }
+            let mut transport_dumps = Vec::with_capacity(transports.len());
+            for transport in &transports {
+                transport_dumps.push(dumped(transport.dump().await));
+            }
+            let mut consumer_dumps = Vec::with_capacity(consumers.len());
+            for consumer in &consumers {
+                consumer_dumps.push(dumped(consumer.dump().await));
+            }
+            let connection = match connection {
+                Some(connection) => dumped(connection.send(DebugState).await),
+                None => serde_json::Value::Null,
+            };
+
+            peers.push(serde_json::json!({
+                "peerId": peer_id,
+                "sessionId": session_id,
+                "metadata": metadata,
+                "connection": connection,
+                "transports": transport_dumps,
+                "producers": producer_dumps,
+                "consumers": consumer_dumps,
+            }));
+        }
+
+        Ok(serde_json::json!({
+            "id": self.inner.id,
+            "host": self.host(),
+            "speakers": self.speakers(),
+            "hands": self.hands(),
+            "layerCap": self.layer_cap(),
+            "playback": self.playback_state(),
+            "router": router,
+            "peers": peers,
+        }))
+    }
 
-        self.inner.handlers.notification.call_simple(&notification);
+    /// Looks up the object `target` refers to among every peer's producers, consumers and
+    /// transports.
+    pub fn stats_source(&self, target: &StatsTarget) -> Option<StatsSource> {
+        let clients = self.inner.clients.lock();
+        let clients = clients.values();
+        match *target {
+            StatsTarget::Producer { id } => clients
+                .flat_map(|client| &client.producers)
+                .find(|producer| producer.id() == id)
+                .cloned()
+                .map(StatsSource::Producer),
+            StatsTarget::Consumer { id } => clients
+                .flat_map(|client| &client.consumers)
+                .filter_map(WeakConsumer::upgrade)
+                .find(|consumer| consumer.id() == id)
+                .map(StatsSource::Consumer),
+            StatsTarget::Transport { id } => clients
+                .flat_map(|client| &client.transports)
+                .filter_map(WeakWebRtcTransport::upgrade)
+                .find(|transport| transport.id() == id)
+                .map(StatsSource::Transport),
+        }
     }
 
-    pub fn add_producer(&self, peer_id: PeerId, producer: Producer) {
+    /// Peer owning `producer_id` along with the producer's labels.
+    pub fn producer_owner(&self, producer_id: &ProducerId) -> Option<(PeerId, ProducerInfo)> {
         self.inner
             .clients
             .lock()
-            .entry(peer_id.clone())
-            .or_default()
-            .push(producer.clone());
+            .iter()
+            .find_map(|(peer_id, client)| {
+                client
+                    .producers
+                    .iter()
+                    .find(|producer| &producer.id() == producer_id)
+                    .map(|producer| (peer_id.clone(), ProducerInfo::of(producer)))
+            })
+    }
+
+    /// Every peer, most recent dominant speaker first and the rest in join order.
+    pub fn speakers(&self) -> Vec<PeerId> {
+        self.inner.speakers.lock().clone()
+    }
+
+    pub fn last_n(&self) -> Option<usize> {
+        self.inner.config.last_n
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.inner.config
+    }
+
+    /// Whether peers should report their outgoing bitrate through
+    /// [`Vc::report_outgoing_bitrate`].
+    pub fn has_budget(&self) -> bool {
+        self.inner.budget.is_some()
+    }
 
+    /// Highest spatial layer consumers should currently receive, `None` when unrestricted.
+    pub fn layer_cap(&self) -> Option<u8> {
+        self.inner
+            .budget
+            .as_ref()
+            .and_then(|budget| budget.lock().layer_cap())
+    }
+
+    /// Feeds a session's consumer transport send bitrate into the room budget.
+    pub fn report_outgoing_bitrate(&self, session_id: SessionId, bitrate: u32) {
+        let Some(budget) = &self.inner.budget else {
+            return;
+        };
+        let Some(layer_cap) = budget.lock().report(session_id, bitrate) else {
+            return;
+        };
+
+        tracing::info!(parent: &self.inner.span, ?layer_cap, "Spatial layer cap changed");
+        self.inner.handlers.layer_cap.call_simple(&layer_cap);
+    }
+
+    /// Warns every peer that the server is shutting down.
+    pub fn going_away(&self, notice: &GoingAway) {
+        self.inner.handlers.going_away.call_simple(notice);
+    }
+
+    /// Closes every peer's connection.
+    pub fn disconnect_all(&self, reason: &str) {
         self.inner
             .handlers
-            .producer_add
-            .call_simple(&peer_id, &producer);
+            .disconnect
+            .call_simple(&reason.to_string());
     }
 
-    pub fn remove_peer(&self, peer_id: &PeerId) {
-        let producers = self.inner.clients.lock().remove(peer_id);
+    fn set_dominant_speaker(&self, producer_id: &ProducerId) {
+        let Some((peer_id, _)) = self.producer_owner(producer_id) else {
+            return;
+        };
 
-        for producer in producers.unwrap_or_default() {
+        let speakers = {
+            let mut speakers = self.inner.speakers.lock();
+            if speakers.first() == Some(&peer_id) {
+                return;
+            }
+            speakers.retain(|p| p != &peer_id);
+            speakers.insert(0, peer_id.clone());
+            speakers.clone()
+        };
+
+        self.emit(Notification::DominantSpeaker { peer_id });
+        self.inner.handlers.speakers.call_simple(&speakers);
+    }
+
+    /// Removes `peer_id` unless it has been taken over by a different session in the meantime.
+    pub fn remove_peer(&self, peer_id: &PeerId, session_id: SessionId) {
+        if let Some(budget) = &self.inner.budget {
+            budget.lock().forget(session_id);
+        }
+        self.inner.kicked.lock().remove(&session_id);
+
+        let client = {
+            let mut clients = self.inner.clients.lock();
+            match clients.get(peer_id) {
+                Some(client) if client.session_id == session_id => clients.remove(peer_id),
+                _ => None,
+            }
+        };
+
+        if let Some(client) = client {
+            self.remove_client(peer_id, client);
+            self.reassign_host(peer_id);
+            self.inner.chat.lock().forget_peer(peer_id);
+            if self.inner.signals.lock().forget_peer(peer_id) {
+                self.emit(Notification::HandLower {
+                    peer_id: peer_id.clone(),
+                });
+            }
+
+            let speakers = {
+                let mut speakers = self.inner.speakers.lock();
+                speakers.retain(|p| p != peer_id);
+                speakers.clone()
+            };
+            self.inner.handlers.speakers.call_simple(&speakers);
+
+            if self.inner.clients.lock().is_empty() {
+                self.linger(self.inner.empty_timeout);
+            }
+        }
+    }
+
+    /// Keeps the empty room open for `timeout`, letting it close afterwards unless a peer joined
+    /// in the meantime. A zero timeout closes it as soon as its last peer is gone.
+    fn linger(&self, timeout: Duration) {
+        if timeout.is_zero() || self.is_closed() {
+            return;
+        }
+
+        let emptied_at = Instant::now();
+        *self.inner.emptied_at.lock() = Some(emptied_at);
+        *self.inner.keep_alive.lock() = Some(self.clone());
+
+        let vc = self.downgrade();
+        actix::spawn(async move {
+            actix_web::rt::time::sleep(timeout).await;
+            let Some(vc) = vc.upgrade() else {
+                return;
+            };
+            if *vc.inner.emptied_at.lock() == Some(emptied_at) {
+                tracing::info!(parent: vc.span(), "Vc empty for {timeout:?}, closing");
+                let keep_alive = vc.inner.keep_alive.lock().take();
+                drop(keep_alive);
+            }
+        });
+    }
+
+    /// Evicts every peer with `reason` and lets the room close once they are gone. New peers are
+    /// turned away from then on.
+    pub fn close(&self, reason: &str) {
+        if self.inner.closed.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        tracing::info!(parent: self.span(), reason, "Closing vc");
+
+        self.inner.emptied_at.lock().take();
+        let keep_alive = self.inner.keep_alive.lock().take();
+        drop(keep_alive);
+        self.inner
+            .handlers
+            .room_closed
+            .call_simple(&reason.to_string());
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Relaxed)
+    }
+
+    /// Hands the host role to the longest connected remaining peer if `leaving` held it.
+    fn reassign_host(&self, leaving: &PeerId) {
+        let mut host = self.inner.host.lock();
+        if host.as_ref() != Some(leaving) {
+            return;
+        }
+
+        *host = self
+            .inner
+            .clients
+            .lock()
+            .iter()
+            .min_by_key(|(_, client)| client.session_id)
+            .map(|(peer_id, _)| peer_id.clone());
+        let new_host = host.clone();
+        drop(host);
+
+        if let Some(peer_id) = new_host {
+            self.emit(Notification::HostChange { peer_id });
+        }
+    }
+
+    fn remove_client(&self, peer_id: &PeerId, client: Client) {
+        for producer in client.producers {
             let producer_id = &producer.id();
             self.inner
                 .handlers
@@ -154,17 +1003,22 @@ impl Vc {
                 .call_simple(peer_id, producer_id);
         }
 
-        self.inner
-            .handlers
-            .notification
-            .call_simple(&Notification::PeerLeave {
-                peer_id: peer_id.clone(),
-            });
+        self.emit(Notification::PeerLeave {
+            peer_id: peer_id.clone(),
+        });
     }
 
     pub fn remove_producer(&self, peer_id: &PeerId, producer_id: &ProducerId) {
-        if let Some(producers) = self.inner.clients.lock().get_mut(peer_id) {
-            producers.retain(|p| &p.id() != producer_id);
+        let removed = match self.inner.clients.lock().get_mut(peer_id) {
+            Some(client) => {
+                let len = client.producers.len();
+                client.producers.retain(|p| &p.id() != producer_id);
+                client.producers.len() != len
+            }
+            None => false,
+        };
+        if !removed {
+            return;
         }
 
         self.inner
@@ -173,29 +1027,40 @@ impl Vc {
             .call_simple(peer_id, producer_id);
     }
 
-    pub fn get_all_producers(&self) -> Vec<(PeerId, ProducerId)> {
+    pub fn get_all_producers(&self) -> Vec<(PeerId, Producer)> {
         self.inner
             .clients
             .lock()
             .iter()
-            .flat_map(|(peer_id, producers)| {
-                producers
+            .flat_map(|(peer_id, client)| {
+                client
+                    .producers
                     .iter()
-                    .map(move |producer| (peer_id.clone(), producer.id()))
+                    .map(move |producer| (peer_id.clone(), producer.clone()))
             })
             .collect()
     }
 
-    pub fn get_all_peers(&self) -> Vec<PeerId> {
+    pub fn peer_count(&self) -> usize {
+        self.inner.clients.lock().len()
+    }
+
+    pub fn get_all_peers(&self) -> Vec<(PeerId, PeerMetadata, Presence)> {
         self.inner
             .clients
             .lock()
-            .keys()
-            .map(|t| t.clone())
+            .iter()
+            .map(|(peer_id, client)| {
+                (
+                    peer_id.clone(),
+                    client.metadata.clone(),
+                    client.presence.clone(),
+                )
+            })
             .collect()
     }
 
-    pub fn on_notification<F: Fn(&Notification) + Send + Sync + 'static>(
+    pub fn on_notification<F: Fn(&StampedNotification) + Send + Sync + 'static>(
         &self,
         callback: F,
     ) -> HandlerId {
@@ -216,11 +1081,63 @@ impl Vc {
         self.inner.handlers.producer_remove.add(Arc::new(callback))
     }
 
-    pub fn on_echo<F: Fn(&PeerId, &String) + Send + Sync + 'static>(
+    pub fn on_chat<F: Fn(&ChatEvent) + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.inner.handlers.chat.add(Arc::new(callback))
+    }
+
+    pub fn on_playback<F: Fn(&PlaybackState) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.playback.add(Arc::new(callback))
+    }
+
+    pub fn on_speakers_change<F: Fn(&Vec<PeerId>) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.speakers.add(Arc::new(callback))
+    }
+
+    pub fn on_layer_cap_change<F: Fn(&Option<u8>) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.layer_cap.add(Arc::new(callback))
+    }
+
+    /// The reason `session_id` was kicked, if it was replaced before it could listen for kicks.
+    pub fn take_kick(&self, session_id: SessionId) -> Option<String> {
+        self.inner.kicked.lock().remove(&session_id)
+    }
+
+    pub fn on_kick<F: Fn(&PeerId, &SessionId, &String) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.kick.add(Arc::new(callback))
+    }
+
+    pub fn on_going_away<F: Fn(&GoingAway) + Send + Sync + 'static>(
+        &self,
+        callback: F,
+    ) -> HandlerId {
+        self.inner.handlers.going_away.add(Arc::new(callback))
+    }
+
+    pub fn on_disconnect<F: Fn(&String) + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.inner.handlers.disconnect.add(Arc::new(callback))
+    }
+
+    pub fn on_room_closed<F: Fn(&String) + Send + Sync + 'static>(&self, callback: F) -> HandlerId {
+        self.inner.handlers.room_closed.add(Arc::new(callback))
+    }
+
+    pub fn on_settings<F: Fn(&RoomSettings) + Send + Sync + 'static>(
         &self,
         callback: F,
     ) -> HandlerId {
-        self.inner.handlers.echo.add(Arc::new(callback))
+        self.inner.handlers.settings.add(Arc::new(callback))
     }
 
     pub fn on_close<F: FnOnce() + Send + 'static>(&self, callback: F) -> HandlerId {
@@ -1,63 +1,178 @@
 use async_lock::Mutex;
-use mediasoup::prelude::*;
-use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::config::Config;
+use crate::settings::RoomSettings;
 use crate::vc::{Vc, VcId, WeakVc};
+use crate::workers::WorkerPool;
 
-#[derive(Default, Clone)]
+/// Why [`VcRegistry::create_vc`] failed, telling the caller's mistakes from the server's.
+#[derive(Debug)]
+pub enum CreateVcError {
+    AlreadyExists(VcId),
+    InvalidSettings(String),
+    /// The server could not open the room, for instance at its room limit.
+    Unavailable(String),
+}
+
+impl fmt::Display for CreateVcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateVcError::AlreadyExists(vc_id) => write!(f, "Vc {vc_id} already exists"),
+            CreateVcError::InvalidSettings(error) | CreateVcError::Unavailable(error) => {
+                f.write_str(error)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct VcRegistry {
+    config: Arc<Config>,
     vcs: Arc<Mutex<HashMap<VcId, WeakVc>>>,
 }
 
 impl VcRegistry {
-    pub async fn get_or_create_vc(
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            vcs: Arc::default(),
+        }
+    }
+
+    /// Every room that is still open.
+    pub async fn vcs(&self) -> Vec<Vc> {
+        self.vcs
+            .lock()
+            .await
+            .values()
+            .filter_map(WeakVc::upgrade)
+            .collect()
+    }
+
+    /// Number of open rooms and of peers across them.
+    pub async fn usage(&self) -> (usize, usize) {
+        let vcs = self.vcs().await;
+        let peers = vcs.iter().map(Vc::peer_count).sum();
+
+        (vcs.len(), peers)
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.vcs().await.is_empty()
+    }
+
+    /// An existing room, never creates one.
+    pub async fn get(&self, vc_id: &VcId) -> Option<Vc> {
+        self.vcs.lock().await.get(vc_id).and_then(WeakVc::upgrade)
+    }
+
+    /// Opens a room for a joining peer unless it exists, see [`Config::auto_create_rooms`].
+    pub async fn get_or_create_vc(&self, workers: &WorkerPool, vc_id: VcId) -> Result<Vc, String> {
+        let mut vcs = self.vcs.lock().await;
+        if let Some(vc) = vcs.get(&vc_id).and_then(WeakVc::upgrade) {
+            return Ok(vc);
+        }
+
+        let empty_timeout = self.config.empty_room_timeout;
+        self.open(
+            &mut vcs,
+            workers,
+            vc_id,
+            empty_timeout,
+            RoomSettings::default(),
+        )
+        .await
+    }
+
+    /// Opens a room ahead of its peers, failing if it already exists. It stays open for
+    /// `empty_timeout` or the configured default unless somebody joins.
+    pub async fn create_vc(
         &self,
-        worker_manager: &WorkerManager,
+        workers: &WorkerPool,
         vc_id: VcId,
-    ) -> Result<Vc, String> {
+        empty_timeout: Option<Duration>,
+        mut settings: RoomSettings,
+    ) -> Result<Vc, CreateVcError> {
+        settings.normalize();
+        settings
+            .media_codecs(crate::media_codecs())
+            .map_err(CreateVcError::InvalidSettings)?;
+
         let mut vcs = self.vcs.lock().await;
-        match vcs.entry(vc_id.clone()) {
-            Entry::Occupied(mut entry) => match entry.get().upgrade() {
-                Some(vc) => Ok(vc),
-                None => {
-                    let vc = Vc::new(worker_manager, vc_id).await?;
-                    entry.insert(vc.downgrade());
-                    vc.on_close({
-                        let vc_id = vc.id();
-                        let vcs = Arc::clone(&self.vcs);
-
-                        move || {
-                            std::thread::spawn(move || {
-                                futures_lite::future::block_on(async move {
-                                    vcs.lock().await.remove(&vc_id);
-                                });
-                            });
-                        }
-                    })
-                    .detach();
-                    Ok(vc)
-                }
-            },
-            Entry::Vacant(entry) => {
-                let vc = Vc::new(worker_manager, vc_id).await?;
-                entry.insert(vc.downgrade());
-                vc.on_close({
-                    let vc_id = vc.id();
-                    let vcs = Arc::clone(&self.vcs);
-
-                    move || {
-                        std::thread::spawn(move || {
-                            futures_lite::future::block_on(async move {
-                                vcs.lock().await.remove(&vc_id);
-                            });
-                        });
-                    }
-                })
-                .detach();
-                Ok(vc)
+        if vcs.get(&vc_id).and_then(WeakVc::upgrade).is_some() {
+            return Err(CreateVcError::AlreadyExists(vc_id));
+        }
+
+        let empty_timeout = empty_timeout.unwrap_or(self.config.empty_room_timeout);
+        self.open(&mut vcs, workers, vc_id, empty_timeout, settings)
+            .await
+            .map_err(CreateVcError::Unavailable)
+    }
+
+    /// Evicts everyone from a room with `reason` and forgets it, so that the id can be reused
+    /// right away. Returns whether the room existed.
+    pub async fn close_vc(&self, vc_id: &VcId, reason: &str) -> bool {
+        let vc = self
+            .vcs
+            .lock()
+            .await
+            .remove(vc_id)
+            .and_then(|vc| vc.upgrade());
+
+        match vc {
+            Some(vc) => {
+                vc.close(reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn open(
+        &self,
+        vcs: &mut HashMap<VcId, WeakVc>,
+        workers: &WorkerPool,
+        vc_id: VcId,
+        empty_timeout: Duration,
+        settings: RoomSettings,
+    ) -> Result<Vc, String> {
+        if let Some(max_rooms) = self.config.capacity.max_rooms {
+            let rooms = vcs.values().filter_map(WeakVc::upgrade).count();
+            if rooms >= max_rooms {
+                return Err(format!("Server is at its limit of {max_rooms} rooms"));
             }
         }
+
+        let vc = Vc::new(
+            workers,
+            vc_id.clone(),
+            Arc::clone(&self.config),
+            empty_timeout,
+            settings,
+        )
+        .await?;
+        vcs.insert(vc_id.clone(), vc.downgrade());
+        vc.on_close({
+            let vcs = Arc::clone(&self.vcs);
+
+            move || {
+                std::thread::spawn(move || {
+                    futures_lite::future::block_on(async move {
+                        let mut vcs = vcs.lock().await;
+                        // The id may have been taken by a new room after this one was closed
+                        if vcs.get(&vc_id).is_some_and(|vc| vc.upgrade().is_none()) {
+                            vcs.remove(&vc_id);
+                        }
+                    });
+                });
+            }
+        })
+        .detach();
+
+        Ok(vc)
     }
 }
@@ -0,0 +1,125 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use event_listener_primitives::HandlerId;
+use mediasoup::prelude::*;
+
+use crate::config::RtcConfig;
+
+/// A worker and, in single-port mode, the WebRtcServer all of its transports go through.
+#[derive(Clone)]
+pub struct PooledWorker {
+    pub worker: Worker,
+    pub webrtc_server: Option<WebRtcServer>,
+    /// Cleared once the worker process dies, see [`Worker::on_dead`].
+    alive: Arc<AtomicBool>,
+}
+
+impl PooledWorker {
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+}
+
+/// Fixed set of mediasoup workers shared by all rooms, handed out round-robin.
+pub struct WorkerPool {
+    workers: Vec<PooledWorker>,
+    next: AtomicUsize,
+    _dead_handlers: Vec<HandlerId>,
+}
+
+impl WorkerPool {
+    /// Starts `config.num_workers` workers. With `config.webrtc_server_port` set, worker `i`
+    /// listens for all of its WebRTC traffic on that port plus `i`, on every configured address.
+    pub async fn new(worker_manager: &WorkerManager, config: &RtcConfig) -> Result<Self, String> {
+        let mut workers = Vec::with_capacity(config.num_workers);
+        let mut dead_handlers = Vec::with_capacity(config.num_workers);
+
+        for index in 0..config.num_workers {
+            let worker = worker_manager
+                .create_worker({
+                    let mut settings = WorkerSettings::default();
+                    settings.log_level = config.worker_log_level;
+                    settings.log_tags = config.worker_log_tags.clone();
+                    if let Some(port_range) = &config.port_range {
+                        settings.rtc_ports_range = port_range.clone();
+                    }
+
+                    settings
+                })
+                .await
+                .map_err(|error| format!("Failed to create worker: {error}"))?;
+
+            let webrtc_server = match config.webrtc_server_port {
+                Some(port) => {
+                    let port = port.checked_add(index as u16).ok_or_else(|| {
+                        format!("WebRtcServer port out of range for worker {index}")
+                    })?;
+                    let listen_infos = config.listen.webrtc_server_listen_infos(port);
+
+                    let webrtc_server = worker
+                        .create_webrtc_server(WebRtcServerOptions::new(listen_infos))
+                        .await
+                        .map_err(|error| {
+                            format!("Failed to create WebRtcServer on port {port}: {error}")
+                        })?;
+                    tracing::info!(worker_id = %worker.id(), port, "WebRtcServer listening");
+
+                    Some(webrtc_server)
+                }
+                None => None,
+            };
+
+            let alive = Arc::new(AtomicBool::new(true));
+            dead_handlers.push(worker.on_dead({
+                let worker_id = worker.id();
+                let alive = Arc::clone(&alive);
+
+                move |result| {
+                    alive.store(false, Ordering::Relaxed);
+                    tracing::error!(%worker_id, ?result, "Worker died");
+                }
+            }));
+
+            workers.push(PooledWorker {
+                worker,
+                webrtc_server,
+                alive,
+            });
+        }
+
+        if workers.is_empty() {
+            return Err("At least one worker is required".to_string());
+        }
+
+        Ok(Self {
+            workers,
+            next: AtomicUsize::new(0),
+            _dead_handlers: dead_handlers,
+        })
+    }
+
+    /// Next live worker in round-robin order.
+    pub fn pick(&self) -> Result<PooledWorker, String> {
+        for _ in 0..self.workers.len() {
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+            let worker = &self.workers[index];
+            if worker.is_alive() {
+                return Ok(worker.clone());
+            }
+        }
+
+        Err("No mediasoup worker alive".to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn alive_count(&self) -> usize {
+        self.workers
+            .iter()
+            .filter(|worker| worker.is_alive())
+            .count()
+    }
+}